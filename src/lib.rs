@@ -0,0 +1,4694 @@
+//! A native module for ML inference on TensorFlow Lite.
+//! Takes input tensors, feeds them to the model and outputs output tensors.
+//!
+//! The inference logic lives in this library so that it can be reused and
+//! tested without going through the `/execution_config` file; the binary is a
+//! thin wrapper around [`run_inference`].
+//!
+//! Features needing TensorFlow Lite APIs that the `tflite` bindings don't
+//! expose yet are listed in the README's "Unsupported by the current
+//! bindings" section, and the code standing in for them is marked by
+//! `Unsupported:` comments naming the missing APIs.
+//!
+//! ## Authors
+//!
+//! The Veracruz Development Team.
+//!
+//! ## Licensing and copyright notice
+//!
+//! See the `LICENSE_MIT.markdown` file in the Veracruz root directory for
+//! information on licensing and copyright.
+
+use anyhow::Context;
+use half::f16;
+use hmac::{Hmac, Mac};
+use libc::c_int;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_reflection::{Tracer, TracerConfig};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tflite::context::{ElementKind, TensorInfo};
+use tflite::op_resolver::OpResolver;
+use tflite::ops::builtin::BuiltinOpResolver;
+use tflite::{FlatBufferModel, Interpreter, InterpreterBuilder, TensorIndex};
+
+mod npy;
+#[cfg(unix)]
+mod server;
+
+/// Module's API.
+/// Fields missing from JSON configurations take their values from
+/// [`TfLiteInferenceService::new`]; postcard configurations list them all.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct TfLiteInferenceService {
+    /// Paths to the input tensors to be fed to the network, in the order of
+    /// the model's inputs. If they are all directories, inference is run on
+    /// each of their files in turn, the outputs being written under the same
+    /// names in the output tensor paths, created as directories. `-` stands
+    /// for stdin.
+    pub input_tensor_paths: Vec<PathBuf>,
+    /// Model serialized with FlatBuffers, optionally gzip-compressed.
+    pub model: ModelSource,
+    /// Paths to the output tensors containing the result of the prediction, in
+    /// the order of the model's outputs. `-` stands for stdout.
+    pub output_tensor_paths: Vec<PathBuf>,
+    /// Number of CPU threads to use for the TensorFlow Lite interpreter.
+    /// -1 lets TensorFlow Lite decide and 0 uses all the available logical
+    /// CPUs.
+    pub num_threads: c_int,
+    /// Optional path to a JSON report of the time spent in each phase of the
+    /// inference.
+    pub timing_report_path: Option<PathBuf>,
+    /// Whether to run the graph through the XNNPACK delegate.
+    /// XNNPACK accelerates float32 operators (convolutions, pooling, fully
+    /// connected layers, element-wise arithmetic...) on the CPU; quantized and
+    /// non-float tensors are left to the builtin kernels.
+    /// Unsupported by the current bindings, see the README.
+    pub use_xnnpack: bool,
+    /// Optional shapes of the model's inputs, in order. Input files must hold
+    /// exactly as many elements as their shape.
+    /// Unsupported by the current bindings, see the README.
+    pub input_shapes: Option<Vec<Vec<i32>>>,
+    /// Optional path to a JSON description of the output tensors (shape,
+    /// element type and quantization parameters), so that consumers can
+    /// interpret the raw output files.
+    pub output_shape_path: Option<PathBuf>,
+    /// Optional path to a CSV dump of per-operator timings (op name, node
+    /// index, microseconds).
+    /// Unsupported by the current bindings, see the README.
+    pub profile_path: Option<PathBuf>,
+    /// Optional element type of the input files, when it differs from the
+    /// type of the input tensors. Float32 files are quantized on the fly when
+    /// fed to uint8 or int8 tensors.
+    pub input_dtype: Option<DType>,
+    /// Whether to apply a softmax over the last dimension of the output tensors
+    /// before writing them, e.g. to turn a classifier's logits into
+    /// probabilities. Only supported for float32 outputs.
+    pub apply_softmax: bool,
+    /// If set, the indices and scores of the `top_k` highest entries of each
+    /// row (along the last dimension) of the output tensors are written as
+    /// JSON instead of the raw tensors.
+    pub top_k: Option<usize>,
+    /// Directory against which all the paths above are resolved, `/` by
+    /// default.
+    pub output_root: Option<PathBuf>,
+    /// If set, `invoke()` is run this many times on the same inputs and the
+    /// latency statistics are reported, instead of a single inference.
+    pub benchmark_runs: Option<u32>,
+    /// Number of discarded runs before the benchmark runs.
+    pub warmup_runs: Option<u32>,
+    /// Optional path to a JSON report of the benchmark statistics. They are
+    /// printed to stdout otherwise.
+    pub benchmark_report_path: Option<PathBuf>,
+    /// Whether to write the output tensors in benchmark mode. Outputs are
+    /// skipped by default to isolate compute cost.
+    pub benchmark_write_outputs: bool,
+    /// Optional hex-encoded SHA-256 digest the model must match to be run,
+    /// pinning the exact model binary allowed to execute. Compressed models
+    /// are checked once decompressed.
+    pub model_sha256: Option<String>,
+    /// Whether the input and output files are NumPy `.npy` files, whose
+    /// shapes and element types are checked against the model's tensors.
+    pub npy_io: bool,
+    /// Optional name of the model signature to run, e.g. `serving_default`.
+    /// Unsupported by the current bindings, see the README.
+    pub signature: Option<String>,
+    /// Optional paths to the input tensors keyed by tensor name, replacing
+    /// `input_tensor_paths` for models whose input order isn't guaranteed.
+    pub named_input_tensor_paths: Option<HashMap<String, PathBuf>>,
+    /// Optional paths to the output tensors keyed by tensor name, replacing
+    /// `output_tensor_paths`.
+    pub named_output_tensor_paths: Option<HashMap<String, PathBuf>>,
+    /// Whether to write float32 output tensors as float16 to save space.
+    /// Values lose precision (about 3 significant decimal digits are kept)
+    /// and those beyond ±65504 become infinite.
+    pub output_as_float16: bool,
+    /// Whether to only load the model and allocate its tensors, reporting
+    /// the input and output tensors' descriptions as JSON without reading
+    /// the inputs or running inference.
+    pub dry_run: bool,
+    /// Optional path to the dry run's report. It is printed to stdout
+    /// otherwise.
+    pub dry_run_report_path: Option<PathBuf>,
+    /// Whether to fail if a floating point output tensor holds NaN or
+    /// infinite values.
+    pub check_output_finite: bool,
+    /// Whether to write the output tensors as text, one element per line,
+    /// for debugging. Booleans are written as `true` or `false`.
+    pub text_output: bool,
+    /// Whether to offload supported operators to the GPU delegate, if built
+    /// with the `gpu` feature. Execution falls back to the CPU if the
+    /// delegate fails to initialize.
+    /// Unsupported by the current bindings, see the README.
+    pub use_gpu: bool,
+    /// Whether to keep the loaded model in an in-process cache keyed by its
+    /// SHA-256 digest, so that repeated runs with an unchanged model skip
+    /// building it. A changed model gets a new cache entry.
+    pub cache_model: bool,
+    /// Optional path to a JSON report of the memory used by the tensors'
+    /// buffers after allocation, in total and per tensor.
+    pub memory_report_path: Option<PathBuf>,
+    /// Format of the output files, raw tensor data by default.
+    pub output_format: OutputFormat,
+    /// Whether to run on a single thread without delegates, overriding
+    /// `num_threads`, `use_xnnpack` and `use_gpu`, so that outputs are
+    /// bitwise reproducible across runs. This may reduce throughput.
+    pub deterministic: bool,
+    /// Optional path to a JSON description of the model's metadata: its
+    /// description, schema version, metadata entries and associated files.
+    /// An empty object is written if the model has no metadata.
+    pub metadata_path: Option<PathBuf>,
+    /// Optional mean subtracted from the elements of the float32 input
+    /// tensors, after decoding them according to `input_dtype`. Must be set
+    /// together with `input_std`.
+    pub input_mean: Option<f32>,
+    /// Optional standard deviation the float32 input tensors' elements are
+    /// divided by, after subtracting `input_mean`.
+    pub input_std: Option<f32>,
+    /// Optional path to a newline-delimited file of class labels, added to
+    /// the entries of top-K outputs.
+    pub labels_path: Option<PathBuf>,
+    /// Optional time limit of each inference, in milliseconds. Running
+    /// inferences can't be interrupted, so an overrun is only reported as an
+    /// error once the inference completes.
+    pub timeout_ms: Option<u64>,
+    /// Whether to dequantize the uint8 and int8 output tensors to float32,
+    /// using their scale and zero point, before any other post-processing.
+    /// Output tensors of other types, float ones in particular, are left
+    /// unchanged.
+    pub dequantize_output: bool,
+    /// Optional CPU cores to pin the inference threads to, on Linux only.
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// Optional path to a JSON summary of the run: model and digest, tensor
+    /// shapes, number of threads, inference duration and, on failure, the
+    /// failing stage. It is written even if the run fails.
+    pub summary_path: Option<PathBuf>,
+    /// Optional list of runs sharing the model and interpreter, each with its
+    /// own input and output paths. If set, the top-level paths must be empty.
+    pub runs: Option<Vec<RunSpec>>,
+    /// Whether to create the missing parent directories of the output files,
+    /// instead of failing.
+    pub create_output_dirs: bool,
+    /// Optional list of the operators the model may use, e.g. `CONV_2D` for
+    /// builtin operators or the name of custom ones. Models using any other
+    /// operator are rejected before inference.
+    pub allowed_ops: Option<Vec<String>>,
+    /// Whether to compute the minimum, maximum, mean and sum of each numeric
+    /// output tensor. They are written as JSON next to each output file, with
+    /// a `.stats.json` suffix, and logged otherwise.
+    pub output_stats: bool,
+    /// Directory against which all the relative paths of the configuration,
+    /// including the model and input paths, are resolved. Absolute paths are
+    /// used as is. This is a clearer name for `output_root`, which it
+    /// replaces when set.
+    pub base_dir: Option<PathBuf>,
+    /// If set, inference is run on the same inputs with each of these numbers
+    /// of threads, instead of writing the outputs, and the invoke latency
+    /// statistics of each are reported. Each number of threads, interpreted as
+    /// `num_threads`, is run once, or `benchmark_runs` times after
+    /// `warmup_runs` discarded runs.
+    pub thread_sweep: Option<Vec<c_int>>,
+    /// Optional path to a JSON report of the thread sweep. It is written to
+    /// stdout otherwise.
+    pub thread_sweep_report_path: Option<PathBuf>,
+    /// Optional paths to reference output tensors, in the order of the
+    /// model's outputs and in their element types. The raw outputs of each
+    /// inference must match them within `tolerance`.
+    pub reference_output_paths: Option<Vec<PathBuf>>,
+    /// Maximum absolute or relative difference between the outputs and the
+    /// reference outputs, 0 by default.
+    pub tolerance: Option<f32>,
+    /// Optional models run after `model`, in order, each fed the output
+    /// tensors of the previous model in memory. The input tensors are fed to
+    /// `model` and the output tensors are those of the last model.
+    pub pipeline: Option<Vec<PipelineStage>>,
+    /// Byte order of the elements of the raw input files, the host's by
+    /// default.
+    pub input_endianness: Option<Endianness>,
+    /// Byte order of the elements of the raw output files, the host's by
+    /// default.
+    pub output_endianness: Option<Endianness>,
+    /// Optional path to a PNG or JPEG image fed to the model's single
+    /// `[1, H, W, C]` input instead of the input tensor files. It is resized
+    /// to the input's shape, and normalized with `input_mean` and `input_std`
+    /// for float32 inputs. Requires the `image` feature.
+    pub input_image_path: Option<PathBuf>,
+    /// Whether to describe every tensor and node of the model's graph, once
+    /// the interpreter is built, as JSON.
+    pub print_graph: bool,
+    /// Optional path to the graph description. It is written to stdout
+    /// otherwise.
+    pub graph_report_path: Option<PathBuf>,
+    /// Whether to append the outputs to the output files instead of
+    /// overwriting them, e.g. to collect the outputs of all the runs or of a
+    /// batch into single files, in which case the output paths are files.
+    pub output_append: bool,
+    /// Optional path to a file receiving the log messages instead of stderr,
+    /// once the execution configuration is parsed. Logs never go to stdout,
+    /// which may carry output tensors and reports.
+    pub log_path: Option<PathBuf>,
+    /// Optional path to a single file holding all the input tensors, read
+    /// instead of the input tensor files. It starts with the number of
+    /// tensors as a little-endian u32, followed by each tensor, in the order of
+    /// the model's inputs, as its length in bytes as a little-endian u64 then
+    /// its data.
+    pub input_blob_path: Option<PathBuf>,
+    /// Optional indices of tensors, e.g. intermediate activations, whose data
+    /// is written to `debug_dir/<index>.bin` after each inference. Only the
+    /// tensors whose buffers aren't reused by the interpreter hold reliable
+    /// data.
+    pub debug_tensors: Option<Vec<usize>>,
+    /// Directory receiving the tensors of `debug_tensors`.
+    pub debug_dir: Option<PathBuf>,
+    /// Optional path to a Unix domain socket on which to serve inference
+    /// requests, with the model loaded once, instead of reading the input
+    /// files, until `SIGTERM` is received. Requests and responses are input
+    /// blobs, as `input_blob_path`, one per connection.
+    pub socket_path: Option<PathBuf>,
+    /// Optional path to a JSON array of the quantization parameters of each
+    /// output tensor, in order, `null` for tensors that aren't quantized.
+    /// Tensors quantized per axis have one scale and zero point per channel.
+    pub output_quant_path: Option<PathBuf>,
+    /// Whether the interpreter should keep every tensor after inference
+    /// instead of reusing their memory.
+    /// Unsupported by the current bindings, see the README.
+    pub preserve_all_tensors: bool,
+    /// Optional file descriptor, already opened for reading by the host, to
+    /// read the model from instead of `model`, so that the model doesn't have
+    /// to be exposed on the VFS. The descriptor isn't closed.
+    pub model_fd: Option<c_int>,
+    /// Optional maximum size of the model in bytes, checked before reading it
+    /// and after decompressing it. Decompressed models are limited to 2 GiB
+    /// if unset.
+    pub max_model_bytes: Option<u64>,
+    /// Optional maximum size of each input file in bytes, checked before
+    /// reading it, or of each input tensor of the requests in server mode.
+    pub max_input_bytes: Option<u64>,
+    /// Optional factor the elements of the float32 output tensors are
+    /// multiplied by, after dequantizing them, so that normalized outputs are
+    /// mapped back to their physical range. Must be set together with
+    /// `output_bias`.
+    pub output_scale: Option<f32>,
+    /// Optional offset added to the float32 output tensors' elements, after
+    /// multiplying them by `output_scale`.
+    pub output_bias: Option<f32>,
+    /// Optional path to a JSON report of the failure, written if the inference
+    /// fails, with the failure's category, message and stage.
+    pub error_report_path: Option<PathBuf>,
+    /// Optional number of workers processing the files of batch inputs in
+    /// parallel, each with its own interpreter sharing the model. The files
+    /// are processed in turn by default.
+    pub batch_workers: Option<usize>,
+    /// Optional positions, among the model's outputs, of the output tensors
+    /// to write, in the order of the output paths. The other outputs are
+    /// ignored. All outputs are written by default.
+    pub output_indices: Option<Vec<usize>>,
+    /// Whether to write the elements of the raw output files in little-endian
+    /// byte order whatever the host's, so that they are portable, as an
+    /// `output_endianness` of `Little`.
+    pub canonical_output_endianness: bool,
+    /// Whether to accept models with dynamic tensors, allocated while running
+    /// the model, `true` if omitted from a JSON configuration.
+    /// Unsupported by the current bindings, see the README.
+    pub allow_dynamic_tensors: bool,
+    /// Whether to write all the raw output tensors, one after the other behind
+    /// a header describing them, to the single output tensor path, for
+    /// transports limited to one output file. See the README for the format.
+    pub concat_outputs: bool,
+    /// Optional time to wait for missing input files to appear, in
+    /// milliseconds, polling with an increasing backoff, for inputs
+    /// provisioned concurrently with the module. Missing inputs fail
+    /// immediately by default.
+    pub input_wait_ms: Option<u64>,
+    /// Operator resolver of the interpreters, `Builtin` by default.
+    pub resolver: Option<ResolverKind>,
+    /// Whether to report the peak resident set size of the process before
+    /// and after the inference, as JSON, including the memory used outside of
+    /// the tensors' buffers. Only supported on Linux.
+    pub report_peak_rss: bool,
+    /// Optional path to the peak resident set size report. It is written to
+    /// stdout otherwise.
+    pub peak_rss_report_path: Option<PathBuf>,
+    /// Optional permission bits of the output files, e.g. `0o600` to keep
+    /// them private, set whatever the umask. Only supported on Unix.
+    pub output_mode: Option<u32>,
+    /// Whether to fill the input tensors with zero bytes instead of reading
+    /// input files, to exercise a model without matching inputs.
+    pub zero_fill_input: bool,
+    /// Optional elements of the model's single input tensor, given inline
+    /// instead of in an input file, converted to the tensor's type and
+    /// quantized if needed.
+    pub inline_input: Option<Vec<f32>>,
+    /// Optional size of the leading, batch dimension to resize the model's
+    /// inputs to, keeping their other dimensions, as a shorthand for
+    /// `input_shapes`.
+    /// Unsupported by the current bindings, see the README.
+    pub batch_size: Option<usize>,
+    /// Optional expected SHA-256 digest, hex-encoded, of the output files'
+    /// contents of each run, concatenated in order, so that bit-exact outputs
+    /// can be asserted across environments. The outputs are only written if
+    /// they match.
+    pub verify_output_sha256: Option<String>,
+    /// Optional number of batch elements fed to the model at a time. Raw
+    /// input files holding more are split along their leading, batch
+    /// dimension into chunks of this size, inference being run on each in
+    /// turn and the outputs concatenated along the same dimension.
+    /// Unsupported by the current bindings, see the README.
+    pub max_batch_chunk: Option<usize>,
+    /// Optional lower bound to clamp the elements of float output tensors to,
+    /// after scaling them.
+    pub output_clamp_min: Option<f32>,
+    /// Optional upper bound to clamp the elements of float output tensors to,
+    /// after scaling them.
+    pub output_clamp_max: Option<f32>,
+    /// Optional directory to cache the allocated tensor arena of each model
+    /// in.
+    /// Unsupported by the current bindings, see the README.
+    pub arena_cache_dir: Option<PathBuf>,
+    /// Whether to overwrite existing output files, including reports and
+    /// other sidecar files, instead of failing, `true` if omitted from a JSON
+    /// configuration. Appended outputs are never truncated anyway.
+    pub overwrite_output: bool,
+    /// Layout of the elements of the raw input files, `Nhwc` by default.
+    /// Inputs are transposed to the model's rank-4 input tensors if their
+    /// layout differs from `model_layout`.
+    pub input_layout: Option<Layout>,
+    /// Layout of the elements of the raw output files, `Nhwc` by default.
+    /// The model's rank-4 output tensors are transposed if their layout,
+    /// `model_layout`, differs.
+    pub output_layout: Option<Layout>,
+    /// Layout of the model's input and output tensors, `Nhwc` by default as
+    /// for most TensorFlow Lite models, or `Nchw` for models converted from
+    /// channels-first toolchains such as PyTorch.
+    pub model_layout: Option<Layout>,
+    /// Optional HMAC-SHA256 of the postcard-serialized fields above, keyed
+    /// with the contents of the file named by `TFLITE_NM_CONFIG_KEY`, and
+    /// required if that variable is set. It must remain the last field, so
+    /// that it covers all the bytes preceding it.
+    pub config_hmac: Option<Vec<u8>>,
+}
+
+/// A model run on the outputs of the previous model of a pipeline.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PipelineStage {
+    /// Model serialized with FlatBuffers, optionally gzip-compressed.
+    pub model: ModelSource,
+    /// Optional expected SHA-256 digest of the model, as `model_sha256`.
+    pub model_sha256: Option<String>,
+    /// Optional number of threads of this stage, as `num_threads`, the
+    /// top-level number of threads being used otherwise.
+    pub num_threads: Option<c_int>,
+}
+
+/// Interpreter of a pipeline stage, with the model it was built for.
+struct StageInterpreter<'a, Op: OpResolver> {
+    model: &'a FlatBufferModel,
+    interpreter: Interpreter<'a, Op>,
+}
+
+/// Input and output paths of one of the runs of an execution configuration.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RunSpec {
+    /// Paths to the input tensors, as `input_tensor_paths`.
+    pub input_tensor_paths: Vec<PathBuf>,
+    /// Paths to the output tensors, as `output_tensor_paths`.
+    pub output_tensor_paths: Vec<PathBuf>,
+}
+
+/// Where to load the model from.
+#[derive(Deserialize, Serialize, Debug)]
+pub enum ModelSource {
+    /// Path to the model on the VFS.
+    Path(PathBuf),
+    /// Model bytes provisioned directly in the execution configuration.
+    Inline(Vec<u8>),
+}
+
+impl Default for ModelSource {
+    fn default() -> Self {
+        Self::Path(PathBuf::new())
+    }
+}
+
+impl fmt::Display for ModelSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelSource::Path(model_path) => write!(f, "{:?}", model_path),
+            ModelSource::Inline(model_bytes) => write!(f, "<inline, {} bytes>", model_bytes.len()),
+        }
+    }
+}
+
+/// Format of the output files.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Raw tensor data, in the host's byte order.
+    #[default]
+    Raw,
+    /// Comma-separated values, one row per entry of the outer dimension, for
+    /// tensors of rank 2 or less.
+    Csv,
+    /// Nested JSON arrays following the tensor's shape.
+    Json,
+    /// NumPy `.npy` file.
+    Npy,
+}
+
+/// Byte order of the elements of a tensor file.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// Whether this is the host's byte order.
+    fn is_native(self) -> bool {
+        (self == Endianness::Little) == cfg!(target_endian = "little")
+    }
+}
+
+/// Order of the dimensions of a rank-4 image tensor: batch, height, width
+/// and channels, or batch, channels, height and width.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// TensorFlow Lite's own layout.
+    #[default]
+    Nhwc,
+    /// The layout of PyTorch and other channels-first toolchains.
+    Nchw,
+}
+
+/// Operator resolver of the interpreter.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResolverKind {
+    /// All the builtin operators, with TensorFlow Lite's default delegates.
+    #[default]
+    Builtin,
+    /// All the builtin operators, without applying the default XNNPACK
+    /// delegate.
+    BuiltinWithoutDefaultDelegates,
+}
+
+/// Element type of a tensor file.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DType {
+    Float32,
+    Int32,
+    UInt8,
+    Int8,
+}
+
+impl DType {
+    /// TensorFlow Lite type of a tensor with this element type.
+    fn element_kind(self) -> ElementKind {
+        match self {
+            DType::Float32 => ElementKind::kTfLiteFloat32,
+            DType::Int32 => ElementKind::kTfLiteInt32,
+            DType::UInt8 => ElementKind::kTfLiteUInt8,
+            DType::Int8 => ElementKind::kTfLiteInt8,
+        }
+    }
+}
+
+/// Class of failure, attached as context to the errors returned by the
+/// module so that callers can handle them programmatically. Classes map to
+/// process exit codes, see [`InferenceError::exit_code`].
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InferenceError {
+    /// The execution configuration couldn't be read, parsed or validated.
+    ConfigParse,
+    /// The model couldn't be loaded or the interpreter couldn't be built.
+    ModelLoad,
+    /// The model's tensors couldn't be allocated.
+    TensorAlloc,
+    /// The input tensors couldn't be read.
+    InputIo,
+    /// The input tensors don't match the model's input tensors.
+    ShapeMismatch,
+    /// Running the model or post-processing its outputs failed.
+    Invoke,
+    /// The output tensors or reports couldn't be written.
+    OutputIo,
+    /// The output tensors diverge from the reference outputs.
+    OutputMismatch,
+    /// Any other failure, not classified above.
+    Other,
+}
+
+impl InferenceError {
+    /// Process exit code of the failure class: 2 for configuration failures,
+    /// 3 for model load failures, 4 for inference failures and 1 otherwise.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            InferenceError::ConfigParse => 2,
+            InferenceError::ModelLoad => 3,
+            InferenceError::TensorAlloc
+            | InferenceError::InputIo
+            | InferenceError::ShapeMismatch
+            | InferenceError::Invoke
+            | InferenceError::OutputIo
+            | InferenceError::OutputMismatch => 4,
+            InferenceError::Other => 1,
+        }
+    }
+
+    /// Stage of the inference that failed, named after the phases of the
+    /// timing report where they match.
+    pub fn stage(self) -> &'static str {
+        match self {
+            InferenceError::ConfigParse => "config",
+            InferenceError::ModelLoad => "model_load",
+            InferenceError::TensorAlloc => "tensor_allocation",
+            InferenceError::InputIo | InferenceError::ShapeMismatch => "input_read",
+            InferenceError::Invoke => "invoke",
+            InferenceError::OutputIo => "output_write",
+            InferenceError::OutputMismatch => "output_check",
+            InferenceError::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for InferenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InferenceError::ConfigParse => write!(f, "invalid execution configuration"),
+            InferenceError::ModelLoad => write!(f, "failed to load model"),
+            InferenceError::TensorAlloc => write!(f, "failed to allocate tensors"),
+            InferenceError::InputIo => write!(f, "failed to read input tensors"),
+            InferenceError::ShapeMismatch => write!(f, "input tensors don't match the model"),
+            InferenceError::Invoke => write!(f, "inference failed"),
+            InferenceError::OutputIo => write!(f, "failed to write output tensors"),
+            InferenceError::OutputMismatch => write!(f, "output tensors don't match the reference"),
+            InferenceError::Other => write!(f, "unexpected failure"),
+        }
+    }
+}
+
+/// Attach `class` to `err` unless it already carries an [`InferenceError`].
+fn classify(err: anyhow::Error, class: InferenceError) -> anyhow::Error {
+    match err.is::<InferenceError>() {
+        true => err,
+        false => err.context(class),
+    }
+}
+
+impl std::error::Error for InferenceError {}
+
+/// Wall-clock duration of each phase of the inference, in microseconds.
+#[derive(Serialize, Debug, Default)]
+struct TimingReport {
+    model_load: u64,
+    tensor_allocation: u64,
+    input_read: u64,
+    invoke: u64,
+    output_write: u64,
+}
+
+impl TimingReport {
+    /// Add the durations of the phases of `other` to those of this report.
+    fn add(&mut self, other: &TimingReport) {
+        self.model_load += other.model_load;
+        self.tensor_allocation += other.tensor_allocation;
+        self.input_read += other.input_read;
+        self.invoke += other.invoke;
+        self.output_write += other.output_write;
+    }
+}
+
+/// Latency statistics of the benchmark runs, in microseconds.
+#[derive(Serialize, Debug)]
+struct BenchmarkReport {
+    runs: usize,
+    min: u64,
+    mean: u64,
+    median: u64,
+    max: u64,
+}
+
+/// Latency statistics of the runs with a given number of threads, in
+/// microseconds.
+#[derive(Serialize, Debug)]
+struct ThreadSweepEntry {
+    num_threads: c_int,
+    #[serde(flatten)]
+    latency: BenchmarkReport,
+}
+
+impl BenchmarkReport {
+    /// Compute the statistics of a non-empty set of durations.
+    fn new(mut durations: Vec<u64>) -> Self {
+        durations.sort_unstable();
+        let runs = durations.len();
+        Self {
+            runs,
+            min: durations[0],
+            mean: durations.iter().sum::<u64>() / runs as u64,
+            median: durations[runs / 2],
+            max: durations[runs - 1],
+        }
+    }
+}
+
+/// Description of a tensor, as written to the output shape file.
+#[derive(Serialize, Debug)]
+struct TensorDescription {
+    index: TensorIndex,
+    name: String,
+    dims: Vec<usize>,
+    /// Element type, named as in `input_dtype`.
+    dtype: &'static str,
+    /// Only set if the tensor is quantized.
+    quantization: Option<Quantization>,
+}
+
+/// Description of an output tensor, in the header of concatenated outputs.
+#[derive(Serialize, Debug)]
+struct ConcatEntry {
+    /// Length of the tensor's data, in bytes.
+    length: usize,
+    dims: Vec<usize>,
+    /// Element type, named as in `input_dtype`.
+    dtype: &'static str,
+}
+
+/// An operator of the model's graph, with the indices of its tensors.
+#[derive(Serialize, Debug)]
+struct NodeDescription {
+    index: usize,
+    op: String,
+    inputs: Vec<i32>,
+    outputs: Vec<i32>,
+}
+
+/// Every tensor and node of the model's graph.
+#[derive(Serialize, Debug)]
+struct GraphReport {
+    tensors: Vec<TensorDescription>,
+    nodes: Vec<NodeDescription>,
+}
+
+/// Description of the model's tensors, as reported by a dry run.
+#[derive(Serialize, Debug)]
+struct DryRunReport {
+    inputs: Vec<TensorDescription>,
+    outputs: Vec<TensorDescription>,
+}
+
+/// Metadata embedded in a model, as written to the metadata file.
+#[derive(Serialize, Debug, Default)]
+struct ModelMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_runtime_version: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    entries: Vec<MetadataEntry>,
+    /// Files, e.g. labels, packed in a zip archive appended to the model.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    associated_files: Vec<String>,
+}
+
+/// Named metadata buffer of a model.
+#[derive(Serialize, Debug)]
+struct MetadataEntry {
+    name: String,
+    bytes: usize,
+}
+
+/// Record of a run, as written to the summary file.
+#[derive(Serialize, Debug, Default)]
+struct Summary {
+    model: String,
+    /// SHA-256 digest of the (decompressed) model.
+    model_sha256: String,
+    input_shapes: Vec<Vec<usize>>,
+    output_shapes: Vec<Vec<usize>>,
+    num_threads: c_int,
+    /// Time spent running the model, in microseconds.
+    invoke: u64,
+    success: bool,
+    /// Stage of the inference that failed, as in error reports. Only set on
+    /// failure.
+    failed_stage: Option<&'static str>,
+}
+
+/// Peak resident set size of the process, in bytes, as written to the peak
+/// RSS report.
+#[derive(Serialize, Debug)]
+struct PeakRssReport {
+    before: u64,
+    after: u64,
+}
+
+/// Memory footprint of the interpreter's tensors after allocation, as
+/// written to the memory report.
+#[derive(Serialize, Debug)]
+struct MemoryReport {
+    total_bytes: usize,
+    tensors: Vec<TensorMemory>,
+}
+
+/// Size of a tensor's buffer, in bytes.
+#[derive(Serialize, Debug)]
+struct TensorMemory {
+    index: TensorIndex,
+    name: String,
+    bytes: usize,
+}
+
+/// Statistics of the values of an output tensor.
+#[derive(Serialize, Debug)]
+struct TensorStats {
+    index: TensorIndex,
+    name: String,
+    min: f64,
+    max: f64,
+    mean: f64,
+    sum: f64,
+}
+
+/// Quantization parameters of a tensor, one entry per channel for tensors
+/// quantized per axis.
+/// Real values are recovered with `scale * (quantized - zero_point)`.
+#[derive(Serialize, Debug)]
+struct Quantization {
+    scale: Vec<f32>,
+    zero_point: Vec<i64>,
+}
+
+/// Tensor path standing for stdin or stdout.
+const STDIO_PATH: &str = "-";
+
+/// Resolve `path` against `root`, unless it stands for stdin or stdout.
+fn resolve_stdio_path(root: &Path, path: &Path) -> PathBuf {
+    match path == Path::new(STDIO_PATH) {
+        true => path.to_path_buf(),
+        false => root.join(path),
+    }
+}
+
+/// Magic bytes starting gzip-compressed files.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Limit of the decompressed model's size if `max_model_bytes` isn't set, as
+/// FlatBuffers can't exceed 2 GiB anyway.
+const DEFAULT_MAX_INFLATED_MODEL_BYTES: u64 = 2 << 30;
+
+/// Decompress `model_bytes` if they are gzip-compressed, failing if the
+/// decompressed model holds more than `limit` bytes, or more than
+/// [`DEFAULT_MAX_INFLATED_MODEL_BYTES`] if no limit is given.
+fn decompress_model(model_bytes: Vec<u8>, limit: Option<u64>) -> anyhow::Result<Vec<u8>> {
+    let limit = limit.unwrap_or(DEFAULT_MAX_INFLATED_MODEL_BYTES);
+    match model_bytes.starts_with(&GZIP_MAGIC) {
+        true => read_limited(flate2::read::GzDecoder::new(&model_bytes[..]), Some(limit))
+            .context("failed to decompress the gzip-compressed model"),
+        false => Ok(model_bytes),
+    }
+}
+
+/// Models loaded with `cache_model` set, keyed by their SHA-256 digest.
+fn model_cache() -> &'static Mutex<HashMap<[u8; 32], Arc<FlatBufferModel>>> {
+    static MODEL_CACHE: OnceLock<Mutex<HashMap<[u8; 32], Arc<FlatBufferModel>>>> = OnceLock::new();
+    MODEL_CACHE.get_or_init(Default::default)
+}
+
+/// Drop all the models cached by previous runs with `cache_model` set.
+pub fn clear_model_cache() {
+    model_cache()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clear();
+}
+
+/// Unpack the FlatBuffers representation of a model, used to look up
+/// information the interpreter doesn't expose.
+fn unpack_model(model: &FlatBufferModel) -> anyhow::Result<tflite::model::Model> {
+    tflite::model::Model::from_buffer(model.buffer())
+        .ok_or_else(|| anyhow::anyhow!("failed to unpack the model"))
+}
+
+/// Extract the metadata embedded in a model, if any.
+fn model_metadata(model: &FlatBufferModel) -> anyhow::Result<ModelMetadata> {
+    let schema = unpack_model(model)?;
+    let description = schema.description.c_str().to_string_lossy().into_owned();
+    let mut metadata = ModelMetadata::default();
+    for entry in schema.metadata.iter() {
+        let name = entry.name.c_str().to_string_lossy().into_owned();
+        let data = schema
+            .buffers
+            .get(entry.buffer as usize)
+            .map_or(&[][..], |b| &b.data[..]);
+        if name == "min_runtime_version" {
+            let version = String::from_utf8_lossy(data);
+            metadata.min_runtime_version = Some(version.trim_end_matches('\0').to_string());
+        }
+        metadata.entries.push(MetadataEntry {
+            name,
+            bytes: data.len(),
+        });
+    }
+    // Models without any zip archive appended simply have no associated files
+    if let Ok(archive) = zip::ZipArchive::new(Cursor::new(model.buffer())) {
+        metadata.associated_files = archive.file_names().map(str::to_string).collect();
+        metadata.associated_files.sort();
+    }
+    if !description.is_empty() || !metadata.entries.is_empty() {
+        metadata.description = Some(description).filter(|description| !description.is_empty());
+        metadata.version = Some(schema.version);
+    }
+    Ok(metadata)
+}
+
+/// Prefix of the custom codes of the TensorFlow operators run by the Flex
+/// delegate.
+const FLEX_PREFIX: &str = "Flex";
+
+/// Names of the custom operators the model uses that aren't registered with
+/// the builtin resolver.
+fn unregistered_custom_ops(model: &FlatBufferModel) -> anyhow::Result<Vec<String>> {
+    // `BuiltinOpResolver` registers these custom operators on top of the
+    // builtin ones
+    const REGISTERED: &[&str] = &["AudioSpectrogram", "Mfcc", "TFLite_Detection_PostProcess"];
+    let model = unpack_model(model)?;
+    let mut names = Vec::new();
+    for operator_code in model.operator_codes.iter() {
+        if operator_code.builtin_code != tflite::model::BuiltinOperator::BuiltinOperator_CUSTOM {
+            continue;
+        }
+        let name = operator_code
+            .custom_code
+            .c_str()
+            .to_string_lossy()
+            .into_owned();
+        if !REGISTERED.contains(&name.as_str()) && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Name of an operator: the name of the builtin operator without its
+/// `BuiltinOperator_` prefix, or the custom code of custom operators.
+fn operator_name(operator_code: &tflite::model::OperatorCodeT) -> String {
+    use tflite::model::BuiltinOperator;
+    if operator_code.builtin_code == BuiltinOperator::BuiltinOperator_CUSTOM {
+        operator_code
+            .custom_code
+            .c_str()
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        let name = format!("{:?}", operator_code.builtin_code);
+        name.trim_start_matches("BuiltinOperator_").to_string()
+    }
+}
+
+/// Names of the distinct operators used by a model, as [`operator_name`].
+fn operator_names(model: &FlatBufferModel) -> anyhow::Result<Vec<String>> {
+    let model = unpack_model(model)?;
+    let mut names = Vec::new();
+    for operator_code in model.operator_codes.iter() {
+        let name = operator_name(operator_code);
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Look up the quantization parameters of a tensor in the model's main
+/// subgraph, if it is quantized.
+fn quantization(model: &tflite::model::Model, index: TensorIndex) -> Option<Quantization> {
+    let tensor = model.subgraphs.first()?.tensors.get(index as usize)?;
+    if !tensor.quantization.is_valid() || tensor.quantization.scale.is_empty() {
+        return None;
+    }
+    Some(Quantization {
+        scale: tensor.quantization.scale.to_vec(),
+        zero_point: tensor.quantization.zero_point.to_vec(),
+    })
+}
+
+/// Quantize native-endian float32 values into a uint8 or int8 tensor, applying
+/// `quantized = round(value / scale) + zero_point`.
+fn quantize(
+    values: &[u8],
+    tensor: &mut [u8],
+    element_kind: ElementKind,
+    quantization: &Quantization,
+) -> anyhow::Result<()> {
+    let (scale, zero_point) = match (&quantization.scale[..], &quantization.zero_point[..]) {
+        (&[scale], &[zero_point]) => (scale, zero_point as f32),
+        _ => anyhow::bail!("per-axis quantized input tensors are not supported"),
+    };
+    let (min, max) = match element_kind {
+        ElementKind::kTfLiteUInt8 => (u8::MIN as f32, u8::MAX as f32),
+        ElementKind::kTfLiteInt8 => (i8::MIN as f32, i8::MAX as f32),
+        _ => anyhow::bail!(
+            "cannot quantize float32 values to {}",
+            type_name(element_kind)
+        ),
+    };
+    for (quantized, value) in tensor.iter_mut().zip(f32s_from_bytes(values)) {
+        let q = ((value / scale).round() + zero_point).clamp(min, max);
+        // Casting through i32 keeps the two's complement representation of
+        // negative int8 values
+        *quantized = q as i32 as u8;
+    }
+    Ok(())
+}
+
+/// Encode `values` as native-endian elements of type `element_kind`,
+/// quantizing them with `quantization` if set and the type is uint8 or int8.
+fn encode_f32s(
+    values: &[f32],
+    element_kind: ElementKind,
+    quantization: Option<&Quantization>,
+) -> anyhow::Result<Vec<u8>> {
+    let quantized = matches!(
+        element_kind,
+        ElementKind::kTfLiteUInt8 | ElementKind::kTfLiteInt8
+    );
+    if let Some(quantization) = quantization.filter(|_| quantized) {
+        let mut tensor = vec![0; values.len()];
+        quantize(
+            &bytes_from_f32s(values),
+            &mut tensor,
+            element_kind,
+            quantization,
+        )?;
+        return Ok(tensor);
+    }
+    let values = values.iter();
+    Ok(match element_kind {
+        ElementKind::kTfLiteFloat32 => values.flat_map(|v| v.to_ne_bytes()).collect(),
+        ElementKind::kTfLiteFloat16 => values
+            .flat_map(|v| f16::from_f32(*v).to_ne_bytes())
+            .collect(),
+        ElementKind::kTfLiteInt64 => values.flat_map(|v| (*v as i64).to_ne_bytes()).collect(),
+        ElementKind::kTfLiteInt32 => values.flat_map(|v| (*v as i32).to_ne_bytes()).collect(),
+        ElementKind::kTfLiteInt16 => values.flat_map(|v| (*v as i16).to_ne_bytes()).collect(),
+        ElementKind::kTfLiteUInt8 => values.map(|v| *v as u8).collect(),
+        ElementKind::kTfLiteInt8 => values.map(|v| *v as i8 as u8).collect(),
+        ElementKind::kTfLiteBool => values.map(|v| (*v != 0.0) as u8).collect(),
+        _ => anyhow::bail!(
+            "cannot convert float32 values to {} elements",
+            type_name(element_kind)
+        ),
+    })
+}
+
+/// Decode native-endian float32 values.
+fn f32s_from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// Encode float32 values in native endianness.
+fn bytes_from_f32s(values: &[f32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_ne_bytes())
+        .collect()
+}
+
+/// Normalize `values` as `(value - mean) / std`.
+fn normalize(values: &mut [f32], mean: f32, std: f32) {
+    values
+        .iter_mut()
+        .for_each(|value| *value = (*value - mean) / std);
+}
+
+/// Map `values` back to their range as `value * scale + bias`.
+fn scale_values(values: &mut [f32], scale: f32, bias: f32) {
+    values
+        .iter_mut()
+        .for_each(|value| *value = *value * scale + bias);
+}
+
+/// Clamp `values` to `min` and `max`, where set.
+fn clamp_values(values: &mut [f32], min: Option<f32>, max: Option<f32>) {
+    let min = min.unwrap_or(f32::NEG_INFINITY);
+    let max = max.unwrap_or(f32::INFINITY);
+    values
+        .iter_mut()
+        .for_each(|value| *value = value.clamp(min, max));
+}
+
+/// Apply a numerically stable softmax to each row of `row_len` values.
+fn softmax(values: &mut [f32], row_len: usize) {
+    for row in values.chunks_mut(row_len.max(1)) {
+        let max = row.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let mut sum = 0.0;
+        for value in row.iter_mut() {
+            *value = (*value - max).exp();
+            sum += *value;
+        }
+        for value in row.iter_mut() {
+            *value /= sum;
+        }
+    }
+}
+
+/// An entry of a top-K output.
+#[derive(Serialize, Debug)]
+struct Prediction {
+    index: usize,
+    /// Only set if a labels file is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    score: f32,
+}
+
+/// Dequantize uint8 or int8 values to float32 values.
+fn dequantize(
+    values: &[u8],
+    element_kind: ElementKind,
+    quantization: &Quantization,
+) -> anyhow::Result<Vec<f32>> {
+    let (scale, zero_point) = match (&quantization.scale[..], &quantization.zero_point[..]) {
+        (&[scale], &[zero_point]) => (scale, zero_point as f32),
+        _ => anyhow::bail!("per-axis quantized output tensors are not supported"),
+    };
+    if !matches!(
+        element_kind,
+        ElementKind::kTfLiteUInt8 | ElementKind::kTfLiteInt8
+    ) {
+        anyhow::bail!("cannot dequantize {} values", type_name(element_kind));
+    }
+    let values = decode_f32s(values, element_kind)?;
+    Ok(values.iter().map(|q| scale * (q - zero_point)).collect())
+}
+
+/// Whether output tensors of type `element_kind` are dequantized by
+/// `dequantize_output`, other tensors, float ones in particular, being passed
+/// through unchanged.
+fn is_dequantized(element_kind: ElementKind) -> bool {
+    matches!(
+        element_kind,
+        ElementKind::kTfLiteUInt8 | ElementKind::kTfLiteInt8
+    )
+}
+
+/// Read a newline-delimited labels file, one label per class.
+fn read_labels(path: &Path) -> anyhow::Result<Vec<String>> {
+    let labels = fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+    let mut labels = labels.lines().map(str::to_string).collect::<Vec<_>>();
+    while labels.last().is_some_and(|label| label.trim().is_empty()) {
+        labels.pop();
+    }
+    Ok(labels)
+}
+
+/// Readable name of the element type `element_kind`, for error messages.
+fn type_name(element_kind: ElementKind) -> &'static str {
+    match element_kind {
+        ElementKind::kTfLiteNoType => "untyped",
+        ElementKind::kTfLiteFloat32 => "Float32",
+        ElementKind::kTfLiteInt32 => "Int32",
+        ElementKind::kTfLiteUInt8 => "UInt8",
+        ElementKind::kTfLiteInt64 => "Int64",
+        ElementKind::kTfLiteString => "String",
+        ElementKind::kTfLiteBool => "Bool",
+        ElementKind::kTfLiteInt16 => "Int16",
+        ElementKind::kTfLiteComplex64 => "Complex64",
+        ElementKind::kTfLiteInt8 => "Int8",
+        ElementKind::kTfLiteFloat16 => "Float16",
+    }
+}
+
+/// Size of an element of the given type, in bytes.
+fn element_size(element_kind: ElementKind) -> Option<usize> {
+    match element_kind {
+        ElementKind::kTfLiteFloat32 | ElementKind::kTfLiteInt32 => Some(4),
+        ElementKind::kTfLiteInt64 => Some(8),
+        ElementKind::kTfLiteFloat16 | ElementKind::kTfLiteInt16 => Some(2),
+        ElementKind::kTfLiteUInt8 | ElementKind::kTfLiteInt8 | ElementKind::kTfLiteBool => Some(1),
+        _ => None,
+    }
+}
+
+/// Reverse the byte order of each element of `bytes`, of `element_size` bytes.
+fn swap_bytes(bytes: &mut [u8], element_size: usize) {
+    for element in bytes.chunks_exact_mut(element_size) {
+        element.reverse();
+    }
+}
+
+/// Transpose the elements of a model's rank-4 tensor, of `element_size`
+/// bytes, between the model's layout and the `data` layout, to the model's
+/// layout if `to_model` is set. `dims` are the model's tensor dimensions.
+fn transpose_layout(
+    bytes: &[u8],
+    dims: &[usize],
+    element_size: usize,
+    (model, data): (Layout, Layout),
+    to_model: bool,
+) -> anyhow::Result<Vec<u8>> {
+    let (n, h, w, c) = match (dims, model) {
+        ([n, h, w, c], Layout::Nhwc) => (*n, *h, *w, *c),
+        ([n, c, h, w], Layout::Nchw) => (*n, *h, *w, *c),
+        _ => anyhow::bail!(
+            "only tensors of rank 4 can be transposed, not of shape {:?}",
+            dims
+        ),
+    };
+    if bytes.len() != n * h * w * c * element_size {
+        anyhow::bail!(
+            "shape {:?} expects {} bytes but {} bytes were supplied",
+            dims,
+            n * h * w * c * element_size,
+            bytes.len()
+        );
+    }
+    let index = |layout, (ni, hi, wi, ci)| match layout {
+        Layout::Nhwc => ((ni * h + hi) * w + wi) * c + ci,
+        Layout::Nchw => ((ni * c + ci) * h + hi) * w + wi,
+    };
+    let mut transposed = vec![0; bytes.len()];
+    for nhwc in 0..n * h * w * c {
+        let element = (
+            nhwc / (h * w * c),
+            nhwc / (w * c) % h,
+            nhwc / c % w,
+            nhwc % c,
+        );
+        let (from, to) = match to_model {
+            true => (index(data, element), index(model, element)),
+            false => (index(model, element), index(data, element)),
+        };
+        transposed[to * element_size..][..element_size]
+            .copy_from_slice(&bytes[from * element_size..][..element_size]);
+    }
+    Ok(transposed)
+}
+
+/// Format the elements of a tensor as text, one string per element.
+fn format_elements(bytes: &[u8], element_kind: ElementKind) -> anyhow::Result<Vec<String>> {
+    fn strings<const N: usize, T: ToString>(bytes: &[u8], f: fn([u8; N]) -> T) -> Vec<String> {
+        bytes
+            .chunks_exact(N)
+            .map(|b| f(b.try_into().unwrap()).to_string())
+            .collect()
+    }
+    Ok(match element_kind {
+        ElementKind::kTfLiteFloat32 => strings(bytes, f32::from_ne_bytes),
+        ElementKind::kTfLiteFloat16 => strings(bytes, f16::from_ne_bytes),
+        ElementKind::kTfLiteInt64 => strings(bytes, i64::from_ne_bytes),
+        ElementKind::kTfLiteInt32 => strings(bytes, i32::from_ne_bytes),
+        ElementKind::kTfLiteInt16 => strings(bytes, i16::from_ne_bytes),
+        ElementKind::kTfLiteInt8 => strings(bytes, i8::from_ne_bytes),
+        ElementKind::kTfLiteUInt8 => strings(bytes, u8::from_ne_bytes),
+        ElementKind::kTfLiteBool => strings(bytes, |[b]: [u8; 1]| b != 0),
+        _ => anyhow::bail!("cannot format {} elements as text", type_name(element_kind)),
+    })
+}
+
+/// Format the elements of a tensor of rank 2 or less as CSV, one row per
+/// entry of the outer dimension.
+fn format_csv(elements: &[String], dims: &[usize]) -> anyhow::Result<String> {
+    let row_len = match dims {
+        [] | [_] => 1,
+        [_, row_len] => (*row_len).max(1),
+        _ => anyhow::bail!("cannot write a tensor of rank {} as CSV", dims.len()),
+    };
+    Ok(elements
+        .chunks(row_len)
+        .map(|row| row.join(",") + "\n")
+        .collect())
+}
+
+/// Format the elements of a tensor as nested JSON arrays following its
+/// shape, NaN and infinite values being written as `null`.
+fn format_json(elements: &[String], dims: &[usize]) -> String {
+    match dims.split_first() {
+        None => match elements.first().map(String::as_str) {
+            Some(element) if element.ends_with("NaN") || element.ends_with("inf") => {
+                "null".to_string()
+            }
+            Some(element) => element.to_string(),
+            None => "null".to_string(),
+        },
+        Some((_, inner_dims)) => {
+            let inner_len = inner_dims.iter().product::<usize>().max(1);
+            let rows = elements
+                .chunks(inner_len)
+                .map(|row| format_json(row, inner_dims))
+                .collect::<Vec<_>>();
+            format!("[{}]", rows.join(","))
+        }
+    }
+}
+
+/// Decode the elements of a numeric tensor as float32 values, without
+/// dequantizing them.
+fn decode_f32s(bytes: &[u8], element_kind: ElementKind) -> anyhow::Result<Vec<f32>> {
+    Ok(match element_kind {
+        ElementKind::kTfLiteFloat32 => f32s_from_bytes(bytes),
+        ElementKind::kTfLiteUInt8 => bytes.iter().map(|b| *b as f32).collect(),
+        ElementKind::kTfLiteInt8 => bytes.iter().map(|b| *b as i8 as f32).collect(),
+        ElementKind::kTfLiteInt32 => bytes
+            .chunks_exact(4)
+            .map(|b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as f32)
+            .collect(),
+        _ => anyhow::bail!("unsupported element type {}", type_name(element_kind)),
+    })
+}
+
+/// Decode the elements of a numeric or boolean tensor as float64 values, which
+/// represent all of them exactly but for large int64 values.
+fn decode_f64s(bytes: &[u8], element_kind: ElementKind) -> anyhow::Result<Vec<f64>> {
+    Ok(match element_kind {
+        ElementKind::kTfLiteFloat32 => f32s_from_bytes(bytes).into_iter().map(f64::from).collect(),
+        ElementKind::kTfLiteFloat16 => bytes
+            .chunks_exact(2)
+            .map(|b| f16::from_ne_bytes([b[0], b[1]]).to_f64())
+            .collect(),
+        ElementKind::kTfLiteInt64 => bytes
+            .chunks_exact(8)
+            .map(|b| i64::from_ne_bytes(b.try_into().unwrap()) as f64)
+            .collect(),
+        ElementKind::kTfLiteInt32 => bytes
+            .chunks_exact(4)
+            .map(|b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        ElementKind::kTfLiteInt16 => bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_ne_bytes([b[0], b[1]]) as f64)
+            .collect(),
+        ElementKind::kTfLiteUInt8 | ElementKind::kTfLiteBool => {
+            bytes.iter().map(|b| *b as f64).collect()
+        }
+        ElementKind::kTfLiteInt8 => bytes.iter().map(|b| *b as i8 as f64).collect(),
+        _ => anyhow::bail!("unsupported element type {}", type_name(element_kind)),
+    })
+}
+
+/// Check that `output` matches `reference`, both tensors of the same type:
+/// each pair of elements may differ by up to `tolerance`, or `tolerance`
+/// times the reference element if greater.
+fn compare_output(
+    output: &[u8],
+    reference: &[u8],
+    element_kind: ElementKind,
+    tolerance: f64,
+) -> anyhow::Result<()> {
+    if output.len() != reference.len() {
+        anyhow::bail!(
+            "output is {} bytes but reference is {}",
+            output.len(),
+            reference.len()
+        );
+    }
+    let output = decode_f64s(output, element_kind)?;
+    let reference = decode_f64s(reference, element_kind)?;
+    let (mut max_index, mut max_diff) = (0, 0.0);
+    let mut diverges = false;
+    for (index, (value, expected)) in output.iter().zip(&reference).enumerate() {
+        let diff = match (value.is_nan(), expected.is_nan()) {
+            (true, true) => 0.0,
+            (false, false) if value == expected => 0.0,
+            _ => (value - expected).abs(),
+        };
+        let diff = if diff.is_nan() { f64::INFINITY } else { diff };
+        diverges |= diff > tolerance.max(tolerance * expected.abs());
+        if diff > max_diff {
+            (max_index, max_diff) = (index, diff);
+        }
+    }
+    if diverges {
+        anyhow::bail!(
+            "output diverges from the reference by up to {} at flat index {}",
+            max_diff,
+            max_index
+        );
+    }
+    Ok(())
+}
+
+/// Select the `k` highest values, in decreasing order.
+/// Only the selected values are sorted, so this stays cheap when `k` is much
+/// smaller than the number of values.
+fn select_top_k(values: &[f32], k: usize) -> Vec<Prediction> {
+    let by_decreasing_score = |a: &usize, b: &usize| values[*b].total_cmp(&values[*a]);
+    let mut indices: Vec<usize> = (0..values.len()).collect();
+    if k < indices.len() {
+        indices.select_nth_unstable_by(k, by_decreasing_score);
+        indices.truncate(k);
+    }
+    indices.sort_unstable_by(by_decreasing_score);
+    indices
+        .into_iter()
+        .map(|index| Prediction {
+            index,
+            label: None,
+            score: values[index],
+        })
+        .collect()
+}
+
+/// Compare two byte strings in constant time, so that the comparison doesn't
+/// leak how many leading bytes match.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Check that the SHA-256 digest of `bytes` matches the hex-encoded `expected`
+/// digest.
+fn verify_sha256(bytes: &[u8], expected: &str) -> anyhow::Result<()> {
+    verify_digest(&Sha256::digest(bytes), expected)
+}
+
+/// Check the SHA-256 `digest` of some bytes against the hex-encoded
+/// `expected` digest.
+fn verify_digest(digest: &[u8], expected: &str) -> anyhow::Result<()> {
+    let expected_digest =
+        hex::decode(expected).with_context(|| format!("invalid SHA-256 digest {:?}", expected))?;
+    if !constant_time_eq(digest, &expected_digest) {
+        anyhow::bail!(
+            "SHA-256 digest mismatch: expected {}, got {}",
+            expected,
+            hex::encode(digest)
+        );
+    }
+    Ok(())
+}
+
+/// Paths of the tensors at `indices`: `paths` if no named paths are given, or
+/// the paths of `named_paths` ordered by tensor name otherwise.
+fn resolve_paths<Op: OpResolver>(
+    interpreter: &Interpreter<Op>,
+    indices: &[TensorIndex],
+    paths: &[PathBuf],
+    named_paths: &Option<HashMap<String, PathBuf>>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let named_paths = match named_paths {
+        Some(named_paths) => named_paths,
+        None => return Ok(paths.to_vec()),
+    };
+    if !paths.is_empty() {
+        anyhow::bail!("tensor paths cannot be given both by position and by name");
+    }
+    let names = indices
+        .iter()
+        .map(|index| Ok(tensor_info(interpreter, *index)?.name))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if let Some(unknown) = named_paths.keys().find(|name| !names.contains(name)) {
+        anyhow::bail!(
+            "the model has no input or output tensor named {:?}",
+            unknown
+        );
+    }
+    names
+        .iter()
+        .map(|name| {
+            named_paths
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("no path was supplied for tensor {:?}", name))
+        })
+        .collect()
+}
+
+/// Measure the tensors' buffers by summing their sizes, constant tensors
+/// included, which ignores the arena's padding and reuse of buffers.
+fn memory_report<Op: OpResolver>(interpreter: &Interpreter<Op>) -> anyhow::Result<MemoryReport> {
+    // Unsupported: the arena's size
+    let tensors = (0..interpreter.tensors_size() as TensorIndex)
+        .map(|index| {
+            Ok(TensorMemory {
+                index,
+                name: tensor_info(interpreter, index)?.name,
+                bytes: interpreter.tensor_buffer(index).map_or(0, <[u8]>::len),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(MemoryReport {
+        total_bytes: tensors.iter().map(|tensor| tensor.bytes).sum(),
+        tensors,
+    })
+}
+
+/// Statistics of each output tensor at `indices`, computed over the raw
+/// values of quantized tensors. Tensors of unsupported types or without
+/// elements have none.
+fn output_stats<Op: OpResolver>(
+    interpreter: &Interpreter<Op>,
+    indices: &[TensorIndex],
+) -> anyhow::Result<Vec<Option<TensorStats>>> {
+    let mut stats = Vec::new();
+    for output_index in indices {
+        let info = tensor_info(interpreter, *output_index)?;
+        let output = interpreter
+            .tensor_buffer(*output_index)
+            .ok_or_else(|| anyhow::anyhow!("invalid output tensor index {}", output_index))?;
+        let values = match decode_f64s(output, info.element_kind) {
+            Ok(values) if !values.is_empty() => values,
+            Ok(_) => {
+                warn!(
+                    "output tensor {} is empty, skipping its statistics",
+                    output_index
+                );
+                stats.push(None);
+                continue;
+            }
+            Err(_) => {
+                warn!(
+                    "statistics of {} output tensor {} are not supported, skipping them",
+                    type_name(info.element_kind),
+                    output_index
+                );
+                stats.push(None);
+                continue;
+            }
+        };
+        let sum = values.iter().sum::<f64>();
+        stats.push(Some(TensorStats {
+            index: *output_index,
+            name: info.name,
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            mean: sum / values.len() as f64,
+            sum,
+        }));
+    }
+    Ok(stats)
+}
+
+/// Fail with the absolute path of `path` if it doesn't exist, as paths
+/// misconfigured relative to the root are otherwise hard to tell apart.
+fn check_exists(path: &Path, what: &str) -> anyhow::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    let path = env::current_dir().map_or_else(|_| path.to_path_buf(), |dir| dir.join(path));
+    anyhow::bail!(
+        "{} {:?} doesn't exist (relative paths are resolved against `base_dir` or \
+         `output_root`, `/` by default)",
+        what,
+        path
+    )
+}
+
+/// Wait up to `wait_ms` milliseconds for `path` to exist, polling with an
+/// exponential backoff, if set.
+fn wait_for(path: &Path, wait_ms: Option<u64>) -> anyhow::Result<()> {
+    let wait_ms = match wait_ms {
+        Some(wait_ms) => wait_ms,
+        None => return Ok(()),
+    };
+    let deadline = Instant::now() + Duration::from_millis(wait_ms);
+    let mut backoff = MIN_WAIT_BACKOFF;
+    if !path.exists() {
+        info!("waiting for {:?}...", path);
+    }
+    while !path.exists() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            anyhow::bail!(
+                "{:?} still doesn't exist after waiting {} ms",
+                path,
+                wait_ms
+            );
+        }
+        thread::sleep(backoff.min(remaining));
+        backoff = (backoff * 2).min(MAX_WAIT_BACKOFF);
+    }
+    Ok(())
+}
+
+/// Read the input files at `input_tensor_paths`, `-` standing for stdin,
+/// waiting up to `input_wait_ms` for them to exist.
+fn read_inputs(
+    input_tensor_paths: &[PathBuf],
+    max_input_bytes: Option<u64>,
+    input_wait_ms: Option<u64>,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    input_tensor_paths
+        .iter()
+        .map(|input_tensor_path| {
+            let input = if input_tensor_path == Path::new(STDIO_PATH) {
+                read_limited(io::stdin(), max_input_bytes)
+            } else {
+                wait_for(input_tensor_path, input_wait_ms).context(InferenceError::InputIo)?;
+                check_exists(input_tensor_path, "input").context(InferenceError::InputIo)?;
+                read_file(input_tensor_path, max_input_bytes)
+            };
+            input
+                .with_context(|| format!("failed to read {:?}", input_tensor_path))
+                .context(InferenceError::InputIo)
+        })
+        .collect()
+}
+
+/// Open the output file at `path` for writing, appending to it if `append`,
+/// and set its permission bits to `mode`, if set and on Unix.
+fn open_output(path: &Path, append: bool, overwrite: bool, mode: Option<u32>) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).append(append).truncate(!append);
+    match append || overwrite {
+        true => options.create(true),
+        false => options.create_new(true),
+    };
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        let file = options.mode(mode).open(path)?;
+        // The mode only applies to new files, masked by the umask
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+        return Ok(file);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    options.open(path)
+}
+
+/// Create the output or report file at `path`, failing if it exists unless
+/// `overwrite` is set.
+pub fn create_file(path: &Path, overwrite: bool) -> io::Result<File> {
+    open_output(path, false, overwrite, None)
+}
+
+/// Read all of `reader`, failing if it holds more than `limit` bytes.
+fn read_limited(reader: impl Read, limit: Option<u64>) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    // One more byte than the limit is read to tell whether it's exceeded
+    reader
+        .take(limit.map_or(u64::MAX, |limit| limit.saturating_add(1)))
+        .read_to_end(&mut bytes)?;
+    if let Some(limit) = limit.filter(|limit| bytes.len() as u64 > *limit) {
+        anyhow::bail!("larger than the limit of {} bytes", limit);
+    }
+    Ok(bytes)
+}
+
+/// Read the file at `path`, checking its size against `limit` beforehand.
+fn read_file(path: &Path, limit: Option<u64>) -> anyhow::Result<Vec<u8>> {
+    let file = File::open(path)?;
+    if let Some(limit) = limit {
+        let len = file.metadata()?.len();
+        if len > limit {
+            anyhow::bail!("{} bytes, larger than the limit of {} bytes", len, limit);
+        }
+    }
+    // The file may still grow while it's read
+    read_limited(file, limit)
+}
+
+/// Concatenate `outputs` behind the little-endian 32-bit length of the JSON
+/// array of `entries` and the array itself, see `concat_outputs`.
+fn concat_tensors(outputs: &[Vec<u8>], entries: &[ConcatEntry]) -> anyhow::Result<Vec<u8>> {
+    let header = serde_json::to_vec(entries)?;
+    let header_len = u32::try_from(header.len())
+        .map_err(|_| anyhow::anyhow!("too many output tensors to concatenate"))?;
+    let mut bytes = header_len.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&header);
+    for output in outputs {
+        bytes.extend_from_slice(output);
+    }
+    Ok(bytes)
+}
+
+/// Split an input blob into its tensors, see `input_blob_path`.
+fn parse_blob(blob: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let truncated = || anyhow::anyhow!("truncated input blob");
+    let count = blob.get(..4).ok_or_else(truncated)?;
+    let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]);
+    let mut rest = &blob[4..];
+    let mut tensors = Vec::new();
+    for _ in 0..count {
+        let len = rest.get(..8).ok_or_else(truncated)?;
+        let len = usize::try_from(u64::from_le_bytes(len.try_into().unwrap()))?;
+        let tensor = rest
+            .get(8..)
+            .and_then(|data| data.get(..len))
+            .ok_or_else(truncated)?;
+        tensors.push(tensor.to_vec());
+        rest = &rest[8 + len..];
+    }
+    if !rest.is_empty() {
+        anyhow::bail!("input blob has {} trailing bytes", rest.len());
+    }
+    Ok(tensors)
+}
+
+/// Decode the PNG or JPEG image at `path` into the raw data of the model's
+/// single `[1, H, W, C]` input, resizing it to `H` by `W`. Pixels are
+/// written as is to uint8 tensors and as float32 values to float32 tensors.
+#[cfg(feature = "image")]
+fn read_image<Op: OpResolver>(
+    interpreter: &Interpreter<Op>,
+    path: &Path,
+    max_bytes: Option<u64>,
+) -> anyhow::Result<Vec<u8>> {
+    let info = match interpreter.inputs() {
+        [input_index] => tensor_info(interpreter, *input_index)?,
+        inputs => anyhow::bail!(
+            "image inputs require a single input tensor, not {}",
+            inputs.len()
+        ),
+    };
+    let (height, width, channels) = match info.dims[..] {
+        [1, height, width, channels] => (height, width, channels),
+        _ => anyhow::bail!(
+            "image inputs require a [1, H, W, C] input tensor, not {:?}",
+            info.dims
+        ),
+    };
+    let bytes = read_file(path, max_bytes).with_context(|| format!("failed to read {:?}", path))?;
+    let image =
+        image::load_from_memory(&bytes).with_context(|| format!("failed to decode {:?}", path))?;
+    let image_channels = image.color().channel_count() as usize;
+    if image_channels != channels {
+        anyhow::bail!(
+            "image {:?} has {} channel(s) but the input tensor expects {}",
+            path,
+            image_channels,
+            channels
+        );
+    }
+    let (width, height) = (u32::try_from(width)?, u32::try_from(height)?);
+    let image = if (image.width(), image.height()) != (width, height) {
+        image.resize_exact(width, height, image::imageops::FilterType::Triangle)
+    } else {
+        image
+    };
+    let pixels = match channels {
+        1 => image.to_luma8().into_raw(),
+        2 => image.to_luma_alpha8().into_raw(),
+        3 => image.to_rgb8().into_raw(),
+        _ => image.to_rgba8().into_raw(),
+    };
+    Ok(match info.element_kind {
+        ElementKind::kTfLiteUInt8 => pixels,
+        ElementKind::kTfLiteFloat32 => {
+            bytes_from_f32s(&pixels.iter().map(|pixel| *pixel as f32).collect::<Vec<_>>())
+        }
+        element_kind => {
+            anyhow::bail!(
+                "image inputs cannot feed {} tensors",
+                type_name(element_kind)
+            )
+        }
+    })
+}
+
+#[cfg(not(feature = "image"))]
+fn read_image<Op: OpResolver>(
+    _: &Interpreter<Op>,
+    path: &Path,
+    _: Option<u64>,
+) -> anyhow::Result<Vec<u8>> {
+    anyhow::bail!(
+        "cannot read image {:?}: the `image` feature is not enabled",
+        path
+    )
+}
+
+/// Peak resident set size of the process so far, in bytes, read from
+/// `VmHWM`. Only supported on Linux.
+#[cfg(target_os = "linux")]
+fn peak_rss() -> anyhow::Result<Option<u64>> {
+    let status = fs::read_to_string("/proc/self/status")?;
+    let vm_hwm = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .ok_or_else(|| anyhow::anyhow!("no VmHWM in /proc/self/status"))?;
+    let kib = vm_hwm.trim().trim_end_matches("kB").trim().parse::<u64>()?;
+    Ok(Some(kib * 1024))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peak_rss() -> anyhow::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Pin the current thread, and the threads it will create, to `cores`.
+#[cfg(target_os = "linux")]
+fn set_cpu_affinity(cores: &[usize]) -> anyhow::Result<()> {
+    // SAFETY: `cpu_set_t` is a plain bit mask, valid when zeroed
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for core in cores {
+        if *core >= libc::CPU_SETSIZE as usize {
+            anyhow::bail!("invalid CPU core {}", core);
+        }
+        // SAFETY: `core` was checked to be within the set
+        unsafe { libc::CPU_SET(*core, &mut set) };
+    }
+    // SAFETY: `set` is a valid `cpu_set_t` of the given size
+    let result = unsafe { libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set) };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to pin inference to CPU cores {:?}", cores));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_cpu_affinity(cores: &[usize]) -> anyhow::Result<()> {
+    warn!(
+        "CPU affinity is not supported on this platform, not pinning to cores {:?}",
+        cores
+    );
+    Ok(())
+}
+
+/// Read all the remaining bytes of the file descriptor `fd`, leaving it open.
+#[cfg(unix)]
+fn read_fd(fd: c_int, limit: Option<u64>) -> anyhow::Result<Vec<u8>> {
+    use std::mem::ManuallyDrop;
+    use std::os::unix::io::FromRawFd;
+
+    // SAFETY: `fcntl` only queries the flags of `fd`, failing if it isn't open
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("invalid model file descriptor {}", fd));
+    }
+    if flags & libc::O_ACCMODE == libc::O_WRONLY {
+        anyhow::bail!("model file descriptor {} is not open for reading", fd);
+    }
+    // SAFETY: `fd` is open, and isn't closed as the file is never dropped
+    let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    read_limited(&*file, limit)
+        .with_context(|| format!("failed to read model file descriptor {}", fd))
+}
+
+#[cfg(not(unix))]
+fn read_fd(fd: c_int, _: Option<u64>) -> anyhow::Result<Vec<u8>> {
+    anyhow::bail!(
+        "cannot read model file descriptor {}: not supported on this platform",
+        fd
+    )
+}
+
+/// First and longest delays between checks for missing inputs.
+const MIN_WAIT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_WAIT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Maximum number of interpreter threads per CPU that batch workers can use.
+const MAX_OVERSUBSCRIPTION: usize = 2;
+
+/// Environment variable naming the file holding the key of `config_hmac`.
+const CONFIG_KEY_VAR: &str = "TFLITE_NM_CONFIG_KEY";
+
+/// Check the `config_hmac` of the postcard-serialized configuration `config`
+/// against `key`, if either is given.
+fn verify_config_hmac(
+    serialized: &[u8],
+    config: &TfLiteInferenceService,
+    key: Option<&[u8]>,
+) -> anyhow::Result<()> {
+    let (config_hmac, key) = match (&config.config_hmac, key) {
+        (None, None) => return Ok(()),
+        (Some(_), None) => anyhow::bail!(
+            "the execution configuration has an HMAC but {} is not set",
+            CONFIG_KEY_VAR
+        ),
+        (None, Some(_)) => anyhow::bail!("the execution configuration has no HMAC"),
+        (Some(config_hmac), Some(key)) => (config_hmac, key),
+    };
+    // The HMAC covers all the bytes preceding its own encoding, as the last
+    // field
+    let encoded_hmac = postcard::to_allocvec(&config.config_hmac)?;
+    let covered = serialized
+        .strip_suffix(&encoded_hmac[..])
+        .ok_or_else(|| anyhow::anyhow!("malformed execution configuration HMAC"))?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)?;
+    mac.update(covered);
+    if !constant_time_eq(&mac.finalize().into_bytes(), config_hmac) {
+        anyhow::bail!("execution configuration HMAC mismatch, the configuration was tampered with");
+    }
+    Ok(())
+}
+
+/// Look up the info of a tensor, failing if the index is invalid.
+fn tensor_info<Op: OpResolver>(
+    interpreter: &Interpreter<Op>,
+    index: TensorIndex,
+) -> anyhow::Result<TensorInfo> {
+    interpreter
+        .tensor_info(index)
+        .ok_or_else(|| anyhow::anyhow!("invalid tensor index {}", index))
+}
+
+/// Convert a failure of TensorFlow Lite, pointing at its own diagnostics.
+fn tflite_error(err: tflite::Error) -> anyhow::Error {
+    // Unsupported: `ErrorReporter`, to capture the messages into the log
+    anyhow::Error::from(err).context("TensorFlow Lite failed, see its messages on stderr, if any")
+}
+
+/// Microseconds elapsed since `start`.
+fn elapsed_us(start: Instant) -> u64 {
+    start.elapsed().as_micros() as u64
+}
+
+impl TfLiteInferenceService {
+    /// Create a new service, with empty internal state.
+    pub fn new() -> Self {
+        Self {
+            input_tensor_paths: Vec::new(),
+            model: ModelSource::default(),
+            output_tensor_paths: Vec::new(),
+            num_threads: -1,
+            timing_report_path: None,
+            use_xnnpack: false,
+            input_shapes: None,
+            output_shape_path: None,
+            profile_path: None,
+            input_dtype: None,
+            apply_softmax: false,
+            top_k: None,
+            output_root: None,
+            benchmark_runs: None,
+            warmup_runs: None,
+            benchmark_report_path: None,
+            benchmark_write_outputs: false,
+            model_sha256: None,
+            npy_io: false,
+            signature: None,
+            named_input_tensor_paths: None,
+            named_output_tensor_paths: None,
+            output_as_float16: false,
+            dry_run: false,
+            dry_run_report_path: None,
+            check_output_finite: false,
+            text_output: false,
+            use_gpu: false,
+            cache_model: false,
+            memory_report_path: None,
+            output_format: OutputFormat::Raw,
+            deterministic: false,
+            metadata_path: None,
+            input_mean: None,
+            input_std: None,
+            labels_path: None,
+            timeout_ms: None,
+            dequantize_output: false,
+            cpu_affinity: None,
+            summary_path: None,
+            runs: None,
+            create_output_dirs: false,
+            allowed_ops: None,
+            output_stats: false,
+            base_dir: None,
+            thread_sweep: None,
+            thread_sweep_report_path: None,
+            reference_output_paths: None,
+            tolerance: None,
+            pipeline: None,
+            input_endianness: None,
+            output_endianness: None,
+            input_image_path: None,
+            print_graph: false,
+            graph_report_path: None,
+            output_append: false,
+            log_path: None,
+            input_blob_path: None,
+            debug_tensors: None,
+            debug_dir: None,
+            socket_path: None,
+            output_quant_path: None,
+            preserve_all_tensors: false,
+            model_fd: None,
+            max_model_bytes: None,
+            max_input_bytes: None,
+            output_scale: None,
+            output_bias: None,
+            error_report_path: None,
+            batch_workers: None,
+            output_indices: None,
+            canonical_output_endianness: false,
+            allow_dynamic_tensors: true,
+            concat_outputs: false,
+            input_wait_ms: None,
+            resolver: None,
+            report_peak_rss: false,
+            peak_rss_report_path: None,
+            output_mode: None,
+            zero_fill_input: false,
+            inline_input: None,
+            batch_size: None,
+            verify_output_sha256: None,
+            max_batch_chunk: None,
+            output_clamp_min: None,
+            output_clamp_max: None,
+            arena_cache_dir: None,
+            overwrite_output: true,
+            input_layout: None,
+            output_layout: None,
+            model_layout: None,
+            config_hmac: None,
+        }
+    }
+
+    /// Try to parse input into a TfLiteInferenceService structure, returning
+    /// `false` if the input is empty and the parsing error if it's malformed.
+    /// The input is parsed as JSON if it starts with `{`, and as postcard
+    /// otherwise.
+    /// If `TFLITE_NM_CONFIG_KEY` names a key file, the input must be postcard
+    /// and is rejected unless its `config_hmac` matches.
+    /// An attacker may inject malformed paths but that should be caught by the
+    /// VFS when attempting to access the corresponding files.
+    /// The input tensor might not match the model's input dimensions, but this
+    /// will be caught by TensorFlow Lite.
+    pub fn try_parse(&mut self, input: &[u8]) -> anyhow::Result<bool> {
+        let key = match env::var_os(CONFIG_KEY_VAR) {
+            Some(key_path) => Some(
+                fs::read(&key_path)
+                    .with_context(|| format!("failed to read the key file {:?}", key_path))?,
+            ),
+            None => None,
+        };
+        let deserialized_input: TfLiteInferenceService = if input.first() == Some(&b'{') {
+            let deserialized_input: TfLiteInferenceService =
+                serde_json::from_slice(input).context("malformed JSON execution configuration")?;
+            if key.is_some() || deserialized_input.config_hmac.is_some() {
+                anyhow::bail!("only postcard execution configurations can be authenticated");
+            }
+            deserialized_input
+        } else {
+            if input.is_empty() {
+                return Ok(false);
+            }
+            let (deserialized_input, rest) = postcard::take_from_bytes(input)
+                .context("malformed postcard execution configuration")?;
+            let serialized = &input[..input.len() - rest.len()];
+            verify_config_hmac(serialized, &deserialized_input, key.as_deref())?;
+            deserialized_input
+        };
+        *self = deserialized_input;
+        Ok(true)
+    }
+
+    /// Check that the model and tensor paths are set, so that malformed
+    /// configurations are rejected before any expensive work.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match &self.model {
+            ModelSource::Path(path) if self.model_fd.is_some() && !path.as_os_str().is_empty() => {
+                anyhow::bail!("`model_fd` cannot be combined with `model`")
+            }
+            ModelSource::Inline(_) if self.model_fd.is_some() => {
+                anyhow::bail!("`model_fd` cannot be combined with `model`")
+            }
+            _ if self.model_fd.is_some() => (),
+            ModelSource::Path(path) if path.as_os_str().is_empty() => {
+                anyhow::bail!("`model` path is empty")
+            }
+            ModelSource::Inline(bytes) if bytes.is_empty() => anyhow::bail!("`model` is empty"),
+            _ => (),
+        }
+        for (i, stage) in self.pipeline.iter().flatten().enumerate() {
+            match &stage.model {
+                ModelSource::Path(path) if path.as_os_str().is_empty() => {
+                    anyhow::bail!("`pipeline[{}].model` path is empty", i)
+                }
+                ModelSource::Inline(bytes) if bytes.is_empty() => {
+                    anyhow::bail!("`pipeline[{}].model` is empty", i)
+                }
+                _ => (),
+            }
+        }
+        for (field, paths) in [
+            ("input_tensor_paths", &self.input_tensor_paths),
+            ("output_tensor_paths", &self.output_tensor_paths),
+        ] {
+            if let Some(i) = paths.iter().position(|path| path.as_os_str().is_empty()) {
+                anyhow::bail!("`{}[{}]` is empty", field, i);
+            }
+        }
+        if self.labels_path.is_some() && self.top_k.is_none() {
+            anyhow::bail!("`labels_path` requires `top_k`");
+        }
+        match (self.input_mean, self.input_std) {
+            (Some(_), None) | (None, Some(_)) => {
+                anyhow::bail!("`input_mean` and `input_std` must be set together")
+            }
+            (_, Some(std)) if std == 0.0 || !std.is_finite() => {
+                anyhow::bail!("invalid `input_std` {}", std)
+            }
+            _ => (),
+        }
+        for (field, named_paths) in [
+            ("named_input_tensor_paths", &self.named_input_tensor_paths),
+            ("named_output_tensor_paths", &self.named_output_tensor_paths),
+        ] {
+            let mut named_paths = named_paths.iter().flatten();
+            if let Some((name, _)) = named_paths.find(|(_, path)| path.as_os_str().is_empty()) {
+                anyhow::bail!("`{}[{:?}]` is empty", field, name);
+            }
+        }
+        match (self.output_scale, self.output_bias) {
+            (Some(_), None) | (None, Some(_)) => {
+                anyhow::bail!("`output_scale` and `output_bias` must be set together")
+            }
+            (Some(scale), Some(bias)) if !scale.is_finite() || !bias.is_finite() => {
+                anyhow::bail!("invalid `output_scale` {} or `output_bias` {}", scale, bias)
+            }
+            _ => (),
+        }
+        if let Some(bound) = [self.output_clamp_min, self.output_clamp_max]
+            .into_iter()
+            .flatten()
+            .find(|bound| bound.is_nan())
+        {
+            anyhow::bail!("invalid output clamping bound {}", bound);
+        }
+        if let (Some(min), Some(max)) = (self.output_clamp_min, self.output_clamp_max) {
+            if min > max {
+                anyhow::bail!(
+                    "`output_clamp_min` {} exceeds `output_clamp_max` {}",
+                    min,
+                    max
+                );
+            }
+        }
+        if let Some(batch_workers) = self.batch_workers {
+            if batch_workers == 0 {
+                anyhow::bail!("`batch_workers` must be positive");
+            }
+            if self.output_append {
+                anyhow::bail!("`batch_workers` cannot be combined with `output_append`");
+            }
+            // Interpreters left to pick their number of threads are counted
+            // as using one
+            let threads = batch_workers.saturating_mul(self.resolved_num_threads().max(1) as usize);
+            let cores = thread::available_parallelism().map_or(1, |n| n.get());
+            if threads > cores * MAX_OVERSUBSCRIPTION {
+                anyhow::bail!(
+                    "{} batch workers with {} thread(s) each would oversubscribe the {} CPU(s)",
+                    batch_workers,
+                    self.resolved_num_threads(),
+                    cores
+                );
+            }
+        }
+        if self.canonical_output_endianness && self.output_endianness == Some(Endianness::Big) {
+            anyhow::bail!("`canonical_output_endianness` requires little-endian outputs");
+        }
+        if self.concat_outputs
+            && (self.npy_io
+                || self.text_output
+                || self.top_k.is_some()
+                || self.output_format != OutputFormat::Raw
+                || self.named_output_tensor_paths.is_some())
+        {
+            anyhow::bail!(
+                "`concat_outputs` only applies to raw outputs written to a single positional path"
+            );
+        }
+        if let Some(output_mode) = self.output_mode {
+            if output_mode & !0o777 != 0 || output_mode & 0o400 == 0 {
+                anyhow::bail!(
+                    "invalid `output_mode` {:#o}, expecting permission bits readable by the owner",
+                    output_mode
+                );
+            }
+            if cfg!(not(unix)) {
+                warn!("`output_mode` is only supported on Unix, ignoring it");
+            }
+        }
+        if self.output_indices.as_ref().is_some_and(Vec::is_empty) {
+            anyhow::bail!("`output_indices` is empty");
+        }
+        if let Some(tolerance) = self.tolerance {
+            if tolerance < 0.0 || !tolerance.is_finite() {
+                anyhow::bail!("invalid `tolerance` {}", tolerance);
+            }
+        }
+        if self.input_image_path.is_some()
+            && (!self.input_tensor_paths.is_empty()
+                || self.named_input_tensor_paths.is_some()
+                || self.runs.is_some()
+                || self.input_dtype.is_some())
+        {
+            anyhow::bail!(
+                "`input_image_path` cannot be combined with input tensor paths, runs or \
+                 `input_dtype`"
+            );
+        }
+        if self.input_blob_path.is_some()
+            && (!self.input_tensor_paths.is_empty()
+                || self.named_input_tensor_paths.is_some()
+                || self.runs.is_some()
+                || self.input_image_path.is_some())
+        {
+            anyhow::bail!(
+                "`input_blob_path` cannot be combined with input tensor paths, runs or image \
+                 inputs"
+            );
+        }
+        if self.zero_fill_input
+            && (!self.input_tensor_paths.is_empty()
+                || self.named_input_tensor_paths.is_some()
+                || self.runs.is_some()
+                || self.input_image_path.is_some()
+                || self.input_blob_path.is_some()
+                || self.input_dtype.is_some()
+                || self.input_mean.is_some()
+                || self.npy_io)
+        {
+            anyhow::bail!(
+                "`zero_fill_input` cannot be combined with input tensor paths, runs, image \
+                 inputs, input blobs or input conversions"
+            );
+        }
+        if self.inline_input.is_some()
+            && (!self.input_tensor_paths.is_empty()
+                || self.named_input_tensor_paths.is_some()
+                || self.runs.is_some()
+                || self.input_image_path.is_some()
+                || self.input_blob_path.is_some()
+                || self.zero_fill_input
+                || self.input_dtype.is_some()
+                || self.input_endianness.is_some()
+                || self.npy_io)
+        {
+            anyhow::bail!(
+                "`inline_input` cannot be combined with input tensor paths, runs, image inputs, \
+                 input blobs, zero-filled inputs or input conversions"
+            );
+        }
+        match self.batch_size {
+            Some(0) => anyhow::bail!("`batch_size` must be positive"),
+            Some(_) if self.input_shapes.is_some() => {
+                anyhow::bail!("`batch_size` cannot be combined with `input_shapes`")
+            }
+            _ => (),
+        }
+        if let Some(profile_path) = &self.profile_path {
+            // Unsupported: `SetProfiler()`
+            anyhow::bail!(
+                "per-operator profiling is not supported by this TensorFlow Lite build, cannot \
+                 write {:?}",
+                profile_path
+            );
+        }
+        if !self.allow_dynamic_tensors {
+            // Unsupported: the tensors' allocation types
+            anyhow::bail!(
+                "rejecting dynamic tensors is not supported by this TensorFlow Lite build, \
+                 set `allow_dynamic_tensors`"
+            );
+        }
+        match self.max_batch_chunk {
+            Some(0) => anyhow::bail!("`max_batch_chunk` must be positive"),
+            Some(_)
+                if self.input_image_path.is_some()
+                    || self.input_blob_path.is_some()
+                    || self.inline_input.is_some()
+                    || self.zero_fill_input
+                    || self.npy_io
+                    || self.text_output
+                    || self.top_k.is_some()
+                    || self.output_format != OutputFormat::Raw
+                    || self.concat_outputs
+                    || self.pipeline.is_some()
+                    || self.benchmark_runs.is_some()
+                    || self.reference_output_paths.is_some()
+                    || self.output_stats =>
+            {
+                anyhow::bail!(
+                    "`max_batch_chunk` only applies to raw input and output files, and cannot be \
+                     combined with pipelines, benchmarks, reference outputs or output statistics"
+                )
+            }
+            _ => (),
+        }
+        if self.npy_io && self.top_k.is_some() {
+            anyhow::bail!("top-K outputs cannot be written as .npy files");
+        }
+        if self.text_output && (self.npy_io || self.top_k.is_some()) {
+            anyhow::bail!("text outputs cannot be combined with .npy files or top-K outputs");
+        }
+        if self.output_format != OutputFormat::Raw && (self.text_output || self.top_k.is_some()) {
+            anyhow::bail!(
+                "{:?} outputs cannot be combined with text or top-K outputs",
+                self.output_format
+            );
+        }
+        if self
+            .input_endianness
+            .is_some_and(|endianness| !endianness.is_native())
+            && (self.npy_io || self.input_image_path.is_some() || self.inline_input.is_some())
+        {
+            anyhow::bail!("`input_endianness` only applies to raw input files");
+        }
+        if self
+            .output_byte_order()
+            .is_some_and(|endianness| !endianness.is_native())
+            && (self.npy_io
+                || self.text_output
+                || self.top_k.is_some()
+                || self.output_format != OutputFormat::Raw)
+        {
+            anyhow::bail!("`output_endianness` only applies to raw output files");
+        }
+        if self.output_as_float16 && self.top_k.is_some() {
+            anyhow::bail!("top-K outputs cannot be converted to float16");
+        }
+        let (model_layout, input_layout) = self.input_layouts();
+        if input_layout != model_layout
+            && (self.npy_io || self.input_image_path.is_some() || self.inline_input.is_some())
+        {
+            anyhow::bail!("`input_layout` only applies to raw input files");
+        }
+        let (model_layout, output_layout) = self.output_layouts();
+        if output_layout != model_layout
+            && (self.npy_io
+                || self.text_output
+                || self.top_k.is_some()
+                || self.output_format != OutputFormat::Raw
+                || self.concat_outputs)
+        {
+            anyhow::bail!("`output_layout` only applies to raw output files");
+        }
+        if self.benchmark_runs == Some(0) {
+            anyhow::bail!("`benchmark_runs` must be positive");
+        }
+        if self.thread_sweep.is_some()
+            && (self.pipeline.is_some()
+                || self.input_image_path.is_some()
+                || self.input_blob_path.is_some()
+                || self.zero_fill_input
+                || self.inline_input.is_some())
+        {
+            anyhow::bail!(
+                "thread sweeps cannot be combined with pipelines, image inputs, input blobs, \
+                 zero-filled inputs or inline inputs"
+            );
+        }
+        if let Some(signature) = &self.signature {
+            // Unsupported: `GetSignatureRunner()`
+            anyhow::bail!(
+                "cannot run signature {:?}: signature runners are not supported by this \
+                 TensorFlow Lite build, use named tensor paths instead",
+                signature
+            );
+        }
+        if self.debug_tensors.is_some() != self.debug_dir.is_some() {
+            anyhow::bail!("`debug_tensors` and `debug_dir` must be set together");
+        }
+        if self.base_dir.is_some() && self.output_root.is_some() {
+            anyhow::bail!("`base_dir` and `output_root` cannot both be set");
+        }
+        if let Some(runs) = &self.runs {
+            if !self.input_tensor_paths.is_empty()
+                || !self.output_tensor_paths.is_empty()
+                || self.named_input_tensor_paths.is_some()
+                || self.named_output_tensor_paths.is_some()
+            {
+                anyhow::bail!("`runs` cannot be combined with top-level tensor paths");
+            }
+            for (i, run) in runs.iter().enumerate() {
+                for (field, paths) in [
+                    ("input_tensor_paths", &run.input_tensor_paths),
+                    ("output_tensor_paths", &run.output_tensor_paths),
+                ] {
+                    if let Some(j) = paths.iter().position(|path| path.as_os_str().is_empty()) {
+                        anyhow::bail!("`runs[{}].{}[{}]` is empty", i, field, j);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Directory against which the configured paths are resolved.
+    pub fn root(&self) -> &Path {
+        let root = self.base_dir.as_deref().or(self.output_root.as_deref());
+        root.unwrap_or_else(|| Path::new("/"))
+    }
+
+    /// Create the output or report file at `path`, failing if it exists unless
+    /// `overwrite_output` is set.
+    pub fn create_file(&self, path: &Path) -> io::Result<File> {
+        create_file(path, self.overwrite_output)
+    }
+
+    /// Layouts of the model's input tensors and of the input files.
+    fn input_layouts(&self) -> (Layout, Layout) {
+        (
+            self.model_layout.unwrap_or_default(),
+            self.input_layout.unwrap_or_default(),
+        )
+    }
+
+    /// Layouts of the model's output tensors and of the output files.
+    fn output_layouts(&self) -> (Layout, Layout) {
+        (
+            self.model_layout.unwrap_or_default(),
+            self.output_layout.unwrap_or_default(),
+        )
+    }
+
+    /// Write `report` as JSON to `path`, resolved against the root.
+    fn write_report(&self, path: &Path, report: &impl Serialize) -> anyhow::Result<()> {
+        let path = self.root().join(path);
+        self.create_file(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| Ok(serde_json::to_writer(file, report)?))
+            .with_context(|| format!("failed to write {:?}", path))
+            .context(InferenceError::OutputIo)
+    }
+
+    /// Number of threads to hand to the interpreter, resolving 0 to the number
+    /// of available logical CPUs. Deterministic runs use a single thread.
+    fn resolved_num_threads(&self) -> c_int {
+        self.resolve_num_threads(self.num_threads)
+    }
+
+    /// Resolve `num_threads` as [`Self::resolved_num_threads`].
+    fn resolve_num_threads(&self, num_threads: c_int) -> c_int {
+        if self.deterministic {
+            return 1;
+        }
+        match num_threads {
+            0 => thread::available_parallelism()
+                .map_or(-1, |n| c_int::try_from(n.get()).unwrap_or(c_int::MAX)),
+            n => n,
+        }
+    }
+
+    /// Description of the top-level model in error messages.
+    fn main_model_name(&self) -> String {
+        match self.model_fd {
+            Some(fd) => format!("<file descriptor {}>", fd),
+            None => self.model.to_string(),
+        }
+    }
+
+    /// Read the top-level model, from `model_fd` if set and from `model`
+    /// otherwise, along with its SHA-256 digest if `summary_path` is set.
+    fn load_main_model(&self) -> anyhow::Result<(Arc<FlatBufferModel>, Option<[u8; 32]>)> {
+        let model_bytes = match self.model_fd {
+            Some(fd) => read_fd(fd, self.max_model_bytes).context(InferenceError::ModelLoad)?,
+            None => self.read_model(&self.model)?,
+        };
+        let model_sha256 = self.model_sha256.as_deref();
+        self.build_model(model_bytes, model_sha256, self.summary_path.is_some())
+    }
+
+    /// Read the model described by `model` and check it against
+    /// `model_sha256`, if set.
+    fn load_model(
+        &self,
+        model: &ModelSource,
+        model_sha256: Option<&str>,
+    ) -> anyhow::Result<Arc<FlatBufferModel>> {
+        let (model, _) = self.build_model(self.read_model(model)?, model_sha256, false)?;
+        Ok(model)
+    }
+
+    /// Read the bytes of the model described by `model`.
+    fn read_model(&self, model: &ModelSource) -> anyhow::Result<Vec<u8>> {
+        let model_bytes = match model {
+            ModelSource::Path(model_path) => {
+                let model_path = self.root().join(model_path);
+                check_exists(&model_path, "model").context(InferenceError::ModelLoad)?;
+                read_file(&model_path, self.max_model_bytes)
+                    .with_context(|| format!("failed to read model {:?}", model_path))
+                    .context(InferenceError::ModelLoad)?
+            }
+            ModelSource::Inline(model_bytes) => {
+                let limit = self
+                    .max_model_bytes
+                    .filter(|limit| model_bytes.len() as u64 > *limit);
+                if let Some(limit) = limit {
+                    return Err(anyhow::anyhow!(
+                        "inline model is {} bytes, larger than the limit of {} bytes",
+                        model_bytes.len(),
+                        limit
+                    ))
+                    .context(InferenceError::ModelLoad);
+                }
+                model_bytes.clone()
+            }
+        };
+        Ok(model_bytes)
+    }
+
+    /// Build a model from `model_bytes`, decompressing them if needed, and
+    /// check it against `model_sha256`, if set. The model's SHA-256 digest is
+    /// returned if computed, as it is if `digest_wanted` is set.
+    fn build_model(
+        &self,
+        model_bytes: Vec<u8>,
+        model_sha256: Option<&str>,
+        digest_wanted: bool,
+    ) -> anyhow::Result<(Arc<FlatBufferModel>, Option<[u8; 32]>)> {
+        let model_bytes = decompress_model(model_bytes, self.max_model_bytes)
+            .context(InferenceError::ModelLoad)?;
+        if !digest_wanted && model_sha256.is_none() && !self.cache_model {
+            let model = FlatBufferModel::build_from_buffer(model_bytes);
+            return Ok((Arc::new(model.context(InferenceError::ModelLoad)?), None));
+        }
+        // The model is hashed once, to check it, look it up and report it
+        let digest: [u8; 32] = Sha256::digest(&model_bytes).into();
+        if let Some(model_sha256) = model_sha256 {
+            verify_digest(&digest, model_sha256).context(InferenceError::ModelLoad)?;
+        }
+        if !self.cache_model {
+            let model = FlatBufferModel::build_from_buffer(model_bytes);
+            return Ok((
+                Arc::new(model.context(InferenceError::ModelLoad)?),
+                Some(digest),
+            ));
+        }
+
+        // The interpreter borrows the model and holds the state of a run, so
+        // only the model itself is cached
+        let mut cache = model_cache().lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(model) = cache.get(&digest) {
+            info!("reusing cached model {}...", hex::encode(digest));
+            return Ok((model.clone(), Some(digest)));
+        }
+        let model = FlatBufferModel::build_from_buffer(model_bytes);
+        let model = Arc::new(model.context(InferenceError::ModelLoad)?);
+        cache.insert(digest, model.clone());
+        Ok((model, Some(digest)))
+    }
+
+    /// Build an interpreter for `model`, described as `model_name` in error
+    /// messages, running on `num_threads` threads, configure it and allocate
+    /// its tensors.
+    fn build_interpreter<'a>(
+        &self,
+        model: &'a FlatBufferModel,
+        model_name: &str,
+        resolver: &'a BuiltinOpResolver,
+        num_threads: c_int,
+        timing: &mut TimingReport,
+    ) -> anyhow::Result<Interpreter<'a, &'a BuiltinOpResolver>> {
+        if let Some(allowed_ops) = &self.allowed_ops {
+            let names = operator_names(model).context(InferenceError::ModelLoad)?;
+            if let Some(name) = names.iter().find(|name| !allowed_ops.contains(name)) {
+                return Err(anyhow::anyhow!(
+                    "model {} uses operator {} which is not allowed",
+                    model_name,
+                    name
+                ))
+                .context(InferenceError::ModelLoad);
+            }
+        }
+
+        // Threads inherit the affinity of the thread creating them
+        if let Some(cpu_affinity) = &self.cpu_affinity {
+            set_cpu_affinity(cpu_affinity).context(InferenceError::ConfigParse)?;
+        }
+
+        // The number of threads is handed to the builder, as
+        // `Interpreter::set_num_threads()` prints to stdout
+        let start = Instant::now();
+        let builder =
+            InterpreterBuilder::new(model, resolver).context(InferenceError::ModelLoad)?;
+        let mut interpreter = match builder.build_with_threads(num_threads) {
+            Ok(interpreter) => interpreter,
+            Err(err) => {
+                // Unsupported: `MutableOpResolver::AddCustom()`, custom
+                // operators being at least named
+                let err = match unregistered_custom_ops(model).as_deref() {
+                    Ok([]) | Err(_) => tflite_error(err),
+                    // Unsupported: the Flex delegate, running the TensorFlow
+                    // operators of models converted with `SELECT_TF_OPS`
+                    Ok(names) if names.iter().any(|name| name.starts_with(FLEX_PREFIX)) => {
+                        let flex = names.iter().filter(|name| name.starts_with(FLEX_PREFIX));
+                        #[cfg(feature = "flex")]
+                        let reason = "the Flex delegate is not supported by this TensorFlow Lite \
+                                      build";
+                        #[cfg(not(feature = "flex"))]
+                        let reason = "the `flex` feature is not enabled";
+                        anyhow::Error::from(err).context(format!(
+                            "model {} uses TensorFlow operator(s) {}, which require the Flex \
+                             delegate, but {}",
+                            model_name,
+                            flex.cloned().collect::<Vec<_>>().join(", "),
+                            reason
+                        ))
+                    }
+                    Ok(names) => anyhow::Error::from(err).context(format!(
+                        "model {} uses custom operator(s) {} which are not supported by \
+                         this TensorFlow Lite build",
+                        model_name,
+                        names.join(", ")
+                    )),
+                };
+                return Err(err.context(InferenceError::ModelLoad));
+            }
+        };
+        timing.model_load += elapsed_us(start);
+
+        // Configure interpreter
+        if self.deterministic && (self.use_xnnpack || self.use_gpu) {
+            info!("deterministic execution, delegates are disabled");
+        }
+        if self.use_xnnpack && !self.deterministic {
+            // Unsupported: `ModifyGraphWithDelegate()`
+            warn!(
+                "the XNNPACK delegate is not supported by this \
+                 TensorFlow Lite build, falling back to the builtin kernels"
+            );
+        }
+
+        if self.use_gpu && !self.deterministic {
+            // Unsupported: `TfLiteGpuDelegateV2Create()`
+            #[cfg(feature = "gpu")]
+            warn!(
+                "the GPU delegate is not supported by this TensorFlow Lite build, \
+                 falling back to CPU execution"
+            );
+            #[cfg(not(feature = "gpu"))]
+            warn!("the `gpu` feature is not enabled, falling back to CPU execution");
+        }
+
+        if self.preserve_all_tensors {
+            // Unsupported: `InterpreterOptions`
+            warn!(
+                "preserving all tensors is not supported by this TensorFlow Lite build, \
+                 intermediate tensors may be overwritten"
+            );
+        }
+
+        if let Some(batch_size) = self.batch_size {
+            self.check_batch_size(&interpreter, batch_size)
+                .context(InferenceError::ConfigParse)?;
+        }
+
+        if let Some(input_shapes) = &self.input_shapes {
+            self.check_input_shapes(&interpreter, input_shapes)
+                .context(InferenceError::ShapeMismatch)?;
+        }
+
+        if let Some(arena_cache_dir) = &self.arena_cache_dir {
+            // Unsupported: the tensor arena
+            warn!(
+                "restoring cached tensor arenas is not supported by this TensorFlow Lite build, \
+                 not using {:?}",
+                arena_cache_dir
+            );
+        }
+        let start = Instant::now();
+        let allocated = interpreter.allocate_tensors();
+        // Models with dynamic shapes may only be allocated once their inputs
+        // are resized to `input_shapes`, the allocation being retried
+        // Unsupported: `ResizeInputTensor()`
+        if allocated.is_err() && self.input_shapes.is_some() {
+            warn!(
+                "retrying tensor allocation after resizing the inputs to `input_shapes` is not \
+                 supported by this TensorFlow Lite build"
+            );
+        }
+        allocated
+            .map_err(tflite_error)
+            .context(InferenceError::TensorAlloc)?;
+        timing.tensor_allocation += elapsed_us(start);
+
+        Ok(interpreter)
+    }
+
+    /// Parse the `.npy` input files, checking their shapes and element types
+    /// against the model's input tensors, and return their data.
+    fn strip_npy_headers<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        inputs: &[Vec<u8>],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let input_indices = interpreter.inputs();
+        inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                // Extra inputs are rejected when fed to the model
+                let input_index = match input_indices.get(i) {
+                    Some(input_index) => *input_index,
+                    None => return Ok(input.clone()),
+                };
+                let (header, data) = npy::parse(input).with_context(|| {
+                    format!("invalid .npy file for input tensor {}", input_index)
+                })?;
+                let info = tensor_info(interpreter, input_index)?;
+                let element_kind = self
+                    .input_dtype
+                    .map_or(info.element_kind, DType::element_kind);
+                if header.element_kind != element_kind {
+                    anyhow::bail!(
+                        "input tensor {} expects {} elements but the .npy file holds {} \
+                         elements",
+                        input_index,
+                        type_name(element_kind),
+                        type_name(header.element_kind)
+                    );
+                }
+                if header.shape != info.dims {
+                    anyhow::bail!(
+                        "input tensor {} has shape {:?} but the .npy file has shape {:?}",
+                        input_index,
+                        info.dims,
+                        header.shape
+                    );
+                }
+                Ok(data.to_vec())
+            })
+            .collect()
+    }
+
+    /// Check that `batch_size` is the batch size of the model's inputs, as
+    /// they can't be resized yet.
+    fn check_batch_size<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        batch_size: usize,
+    ) -> anyhow::Result<()> {
+        for input_index in interpreter.inputs() {
+            let info = tensor_info(interpreter, *input_index)?;
+            match info.dims.first() {
+                None => anyhow::bail!("input tensor {} has no batch dimension", input_index),
+                Some(batch) if *batch == batch_size => (),
+                // Unsupported: `ResizeInputTensor()` and shape signatures
+                Some(batch) => anyhow::bail!(
+                    "resizing the batch dimension of input tensor {} from {} to {} is not \
+                     supported by this TensorFlow Lite build",
+                    input_index,
+                    batch,
+                    batch_size
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that `input_shapes` match the shapes of the model's inputs, as
+    /// they can't be resized yet.
+    fn check_input_shapes<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        input_shapes: &[Vec<i32>],
+    ) -> anyhow::Result<()> {
+        let inputs = interpreter.inputs().to_vec();
+        if inputs.len() != input_shapes.len() {
+            anyhow::bail!(
+                "model {} expects {} input tensor(s) but {} input shape(s) were supplied",
+                self.main_model_name(),
+                inputs.len(),
+                input_shapes.len()
+            );
+        }
+        for (input_index, shape) in inputs.iter().zip(input_shapes.iter()) {
+            let info = tensor_info(interpreter, *input_index)?;
+            if shape.len() != info.dims.len() {
+                anyhow::bail!(
+                    "input tensor {} has rank {} but the supplied shape {:?} has rank {}",
+                    input_index,
+                    info.dims.len(),
+                    shape,
+                    shape.len()
+                );
+            }
+            if shape.iter().any(|dim| *dim < 1) {
+                anyhow::bail!("invalid shape {:?} for input tensor {}", shape, input_index);
+            }
+            // Unsupported: `ResizeInputTensor()`
+            if shape
+                .iter()
+                .map(|dim| *dim as usize)
+                .ne(info.dims.iter().copied())
+            {
+                anyhow::bail!(
+                    "resizing input tensor {} from {:?} to {:?} is not supported by \
+                     this TensorFlow Lite build",
+                    input_index,
+                    info.dims,
+                    shape
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Split `inputs` along their leading, batch dimension into chunks of
+    /// `max_batch_chunk` elements, returning them as is if they already fit
+    /// the model's input tensors.
+    fn batch_chunks<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        max_batch_chunk: usize,
+        inputs: Vec<Vec<u8>>,
+    ) -> anyhow::Result<Vec<Vec<Vec<u8>>>> {
+        let mut chunk_lens = Vec::new();
+        for input_index in interpreter.inputs() {
+            let info = tensor_info(interpreter, *input_index)?;
+            let input_kind = self
+                .input_dtype
+                .map_or(info.element_kind, DType::element_kind);
+            let size = element_size(input_kind).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "cannot split {} inputs into batch chunks",
+                    type_name(input_kind)
+                )
+            })?;
+            let batch = match info.dims.first() {
+                Some(batch) => *batch,
+                None => anyhow::bail!("input tensor {} has no batch dimension", input_index),
+            };
+            let elements = info.dims.iter().skip(1).product::<usize>();
+            chunk_lens.push((*input_index, batch, elements * size));
+        }
+        if inputs.len() != chunk_lens.len() {
+            anyhow::bail!(
+                "model {} expects {} input tensor(s) but {} input(s) were supplied",
+                self.main_model_name(),
+                chunk_lens.len(),
+                inputs.len()
+            );
+        }
+        let fits = chunk_lens.iter().zip(&inputs);
+        if fits
+            .clone()
+            .all(|((_, batch, len), input)| input.len() == batch * len)
+        {
+            return Ok(vec![inputs]);
+        }
+
+        let mut num_chunks = None;
+        for ((input_index, batch, len), input) in chunk_lens.iter().zip(&inputs) {
+            // Unsupported: `ResizeInputTensor()`
+            if *batch != max_batch_chunk {
+                anyhow::bail!(
+                    "resizing the batch dimension of input tensor {} from {} to {} is not \
+                     supported by this TensorFlow Lite build",
+                    input_index,
+                    batch,
+                    max_batch_chunk
+                );
+            }
+            let chunk_len = batch * len;
+            if chunk_len == 0 || input.is_empty() || input.len() % chunk_len != 0 {
+                anyhow::bail!(
+                    "input tensor {} takes chunks of {} bytes but {} bytes were supplied",
+                    input_index,
+                    chunk_len,
+                    input.len()
+                );
+            }
+            match num_chunks {
+                Some(num_chunks) if num_chunks != input.len() / chunk_len => anyhow::bail!(
+                    "input tensor {} is split into {} batch chunk(s) but the previous inputs \
+                     into {}",
+                    input_index,
+                    input.len() / chunk_len,
+                    num_chunks
+                ),
+                _ => num_chunks = Some(input.len() / chunk_len),
+            }
+        }
+
+        let num_chunks = num_chunks.unwrap_or(1);
+        info!("splitting the inputs into {} batch chunk(s)...", num_chunks);
+        Ok((0..num_chunks)
+            .map(|i| {
+                let chunks = chunk_lens.iter().zip(&inputs);
+                chunks
+                    .map(|((_, batch, len), input)| {
+                        let chunk_len = batch * len;
+                        input[i * chunk_len..][..chunk_len].to_vec()
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Copy `inputs` into the model's input tensors, in order, converting them
+    /// according to `input_dtype` if needed.
+    fn feed_inputs<Op: OpResolver>(
+        &self,
+        interpreter: &mut Interpreter<Op>,
+        model: &FlatBufferModel,
+        inputs: &[Vec<u8>],
+    ) -> anyhow::Result<()> {
+        let input_indices = interpreter.inputs().to_vec();
+        if input_indices.len() != inputs.len() {
+            anyhow::bail!(
+                "model {} expects {} input tensor(s) but {} input(s) were supplied",
+                self.main_model_name(),
+                input_indices.len(),
+                inputs.len()
+            );
+        }
+        let schema = match self.input_dtype {
+            Some(_) => Some(unpack_model(model)?),
+            None => None,
+        };
+        for (i, (input_index, input)) in input_indices.iter().zip(inputs.iter()).enumerate() {
+            let info = tensor_info(interpreter, *input_index)?;
+            let element_kind = info.element_kind;
+            // Inputs must match their declared shapes, and not only the size
+            // of the tensors, to catch mis-shaped data
+            let shape = self.input_shapes.as_ref().and_then(|shapes| shapes.get(i));
+            let input_kind = self.input_dtype.map_or(element_kind, DType::element_kind);
+            if let (Some(shape), Some(size)) = (shape, element_size(input_kind)) {
+                let elements = shape.iter().map(|dim| *dim as usize).product::<usize>();
+                if input.len() != elements * size {
+                    anyhow::bail!(
+                        "input tensor {} has the declared shape {:?} of {} {} elements, \
+                         expecting {} bytes, but {} bytes were supplied",
+                        input_index,
+                        shape,
+                        elements,
+                        type_name(input_kind),
+                        elements * size,
+                        input.len()
+                    );
+                }
+            }
+            let layouts = self.input_layouts();
+            let transposed;
+            let input = match layouts.0 == layouts.1 {
+                true => input,
+                false => {
+                    let size = element_size(input_kind).ok_or_else(|| {
+                        anyhow::anyhow!("cannot transpose {} inputs", type_name(input_kind))
+                    })?;
+                    transposed = transpose_layout(input, &info.dims, size, layouts, true)
+                        .with_context(|| {
+                            format!("cannot transpose input tensor {}", input_index)
+                        })?;
+                    &transposed
+                }
+            };
+            let tensor = interpreter
+                .tensor_buffer_mut(*input_index)
+                .ok_or_else(|| anyhow::anyhow!("invalid input tensor index {}", input_index))?;
+            let normalization = self.input_mean.zip(self.input_std);
+            if let (Some((mean, std)), ElementKind::kTfLiteFloat32) = (normalization, element_kind)
+            {
+                let mut values = decode_f32s(input, input_kind)?;
+                if values.len() * 4 != tensor.len() {
+                    anyhow::bail!(
+                        "input tensor {} expects {} elements but {} {} elements were supplied",
+                        input_index,
+                        tensor.len() / 4,
+                        values.len(),
+                        type_name(input_kind)
+                    );
+                }
+                normalize(&mut values, mean, std);
+                tensor.copy_from_slice(&bytes_from_f32s(&values));
+                continue;
+            }
+            match (self.input_dtype, &schema) {
+                (Some(dtype), Some(schema)) if dtype.element_kind() != element_kind => {
+                    if dtype != DType::Float32 {
+                        anyhow::bail!(
+                            "cannot convert {:?} input to {} input tensor {}",
+                            dtype,
+                            type_name(element_kind),
+                            input_index
+                        );
+                    }
+                    // Quantized tensors have one byte per element
+                    if input.len() != tensor.len() * 4 {
+                        anyhow::bail!(
+                            "input tensor {} expects {} float32 elements but {} bytes were \
+                             supplied",
+                            input_index,
+                            tensor.len(),
+                            input.len()
+                        );
+                    }
+                    let quantization = quantization(schema, *input_index).ok_or_else(|| {
+                        anyhow::anyhow!("input tensor {} is not quantized", input_index)
+                    })?;
+                    quantize(input, tensor, element_kind, &quantization)?;
+                }
+                _ => {
+                    if input.len() != tensor.len() {
+                        anyhow::bail!(
+                            "input tensor {} expects {} bytes but {} bytes were supplied",
+                            input_index,
+                            tensor.len(),
+                            input.len()
+                        );
+                    }
+                    tensor.copy_from_slice(input);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the graph once, within `timeout_ms` if set.
+    fn invoke_once<Op: OpResolver>(&self, interpreter: &mut Interpreter<Op>) -> anyhow::Result<()> {
+        let timeout = match self.timeout_ms {
+            Some(timeout_ms) => Duration::from_millis(timeout_ms),
+            None => return interpreter.invoke().map_err(tflite_error),
+        };
+
+        // `Interpreter::invoke()` can't be preempted, so the watchdog can only
+        // warn when the timeout expires, the overrun being reported as an
+        // error once the invocation completes
+        let start = Instant::now();
+        let (done, finished) = mpsc::channel::<()>();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                if finished.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                    warn!("inference is still running after the {:?} timeout", timeout);
+                }
+            });
+            let result = interpreter.invoke();
+            drop(done);
+            result
+        })
+        .map_err(tflite_error)?;
+        let elapsed = start.elapsed();
+        if elapsed > timeout {
+            anyhow::bail!(
+                "inference took {:?}, exceeding the {:?} timeout",
+                elapsed,
+                timeout
+            );
+        }
+        Ok(())
+    }
+
+    /// Run the graph, once or `benchmark_runs` times.
+    fn invoke<Op: OpResolver>(
+        &self,
+        interpreter: &mut Interpreter<Op>,
+        timing: &mut TimingReport,
+    ) -> anyhow::Result<()> {
+        match self.benchmark_runs {
+            None => {
+                info!("invoking...");
+                let start = Instant::now();
+                self.invoke_once(interpreter)
+                    .context(InferenceError::Invoke)?;
+                timing.invoke += elapsed_us(start);
+            }
+            Some(benchmark_runs) => {
+                info!("benchmarking...");
+                for _ in 0..self.warmup_runs.unwrap_or(0) {
+                    self.invoke_once(interpreter)
+                        .context(InferenceError::Invoke)?;
+                }
+                let mut durations = Vec::new();
+                for _ in 0..benchmark_runs {
+                    let start = Instant::now();
+                    self.invoke_once(interpreter)
+                        .context(InferenceError::Invoke)?;
+                    durations.push(elapsed_us(start));
+                }
+                let report = BenchmarkReport::new(durations);
+                timing.invoke += report.mean;
+                match &self.benchmark_report_path {
+                    Some(benchmark_report_path) => {
+                        self.write_report(benchmark_report_path, &report)?;
+                    }
+                    None => println!("{}", serde_json::to_string(&report)?),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the model's output tensors selected by `output_indices`, in order,
+    /// and post-process them according to `dequantize_output`, `output_scale`,
+    /// the output clamping bounds, `apply_softmax` and `top_k`.
+    fn collect_outputs<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        model: &FlatBufferModel,
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let labels = match &self.labels_path {
+            Some(labels_path) => {
+                Some(read_labels(&self.root().join(labels_path)).context(InferenceError::InputIo)?)
+            }
+            None => None,
+        };
+        let schema = match self.dequantize_output {
+            true => Some(unpack_model(model)?),
+            false => None,
+        };
+        let mut outputs = Vec::new();
+        for output_index in &self.selected_outputs(interpreter)? {
+            let info = tensor_info(interpreter, *output_index)?;
+            let mut output = interpreter
+                .tensor_buffer(*output_index)
+                .ok_or_else(|| anyhow::anyhow!("invalid output tensor index {}", output_index))?
+                .to_vec();
+            if self.check_output_finite {
+                let values = match info.element_kind {
+                    ElementKind::kTfLiteFloat32 => f32s_from_bytes(&output),
+                    ElementKind::kTfLiteFloat16 => output
+                        .chunks_exact(2)
+                        .map(|b| f16::from_ne_bytes([b[0], b[1]]).to_f32())
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                let mut non_finite = values.iter().enumerate().filter(|(_, v)| !v.is_finite());
+                if let Some((first, _)) = non_finite.next() {
+                    anyhow::bail!(
+                        "output tensor {} holds {} NaN or infinite value(s), the first at flat \
+                         index {}",
+                        output_index,
+                        non_finite.count() + 1,
+                        first
+                    );
+                }
+            }
+            let mut element_kind = info.element_kind;
+            if let Some(schema) = schema.as_ref().filter(|_| is_dequantized(element_kind)) {
+                let quantization = quantization(schema, *output_index).ok_or_else(|| {
+                    anyhow::anyhow!("output tensor {} is not quantized", output_index)
+                })?;
+                output = bytes_from_f32s(&dequantize(&output, element_kind, &quantization)?);
+                element_kind = ElementKind::kTfLiteFloat32;
+            }
+            if let Some((scale, bias)) = self.output_scale.zip(self.output_bias) {
+                if element_kind != ElementKind::kTfLiteFloat32 {
+                    anyhow::bail!(
+                        "cannot scale {} output tensor {}",
+                        type_name(element_kind),
+                        output_index
+                    );
+                }
+                let mut values = f32s_from_bytes(&output);
+                scale_values(&mut values, scale, bias);
+                output = bytes_from_f32s(&values);
+            }
+            if self.output_clamp_min.is_some() || self.output_clamp_max.is_some() {
+                if element_kind != ElementKind::kTfLiteFloat32 {
+                    anyhow::bail!(
+                        "cannot clamp {} output tensor {}",
+                        type_name(element_kind),
+                        output_index
+                    );
+                }
+                let mut values = f32s_from_bytes(&output);
+                clamp_values(&mut values, self.output_clamp_min, self.output_clamp_max);
+                output = bytes_from_f32s(&values);
+            }
+            if self.apply_softmax {
+                if element_kind != ElementKind::kTfLiteFloat32 {
+                    anyhow::bail!(
+                        "cannot apply softmax to {} output tensor {}",
+                        type_name(element_kind),
+                        output_index
+                    );
+                }
+                let mut values = f32s_from_bytes(&output);
+                softmax(&mut values, info.dims.last().copied().unwrap_or(1));
+                output = bytes_from_f32s(&values);
+            }
+            let layouts = self.output_layouts();
+            if layouts.0 != layouts.1 {
+                let size = element_size(element_kind).ok_or_else(|| {
+                    anyhow::anyhow!("cannot transpose {} outputs", type_name(element_kind))
+                })?;
+                output = transpose_layout(&output, &info.dims, size, layouts, false)
+                    .with_context(|| format!("cannot transpose output tensor {}", output_index))?;
+            }
+            if let Some(k) = self.top_k {
+                let row_len = info.dims.last().copied().unwrap_or(1).max(1);
+                let mut rows = decode_f32s(&output, element_kind)?
+                    .chunks(row_len)
+                    .map(|row| select_top_k(row, k))
+                    .collect::<Vec<_>>();
+                if let Some(labels) = &labels {
+                    if labels.len() != row_len {
+                        anyhow::bail!(
+                            "output tensor {} has {} classes but {} labels were supplied",
+                            output_index,
+                            row_len,
+                            labels.len()
+                        );
+                    }
+                    for prediction in rows.iter_mut().flatten() {
+                        prediction.label = Some(labels[prediction.index].clone());
+                    }
+                }
+                output = serde_json::to_vec(&rows)?;
+            }
+            if self.output_as_float16 {
+                if element_kind != ElementKind::kTfLiteFloat32 {
+                    anyhow::bail!(
+                        "cannot convert {} output tensor {} to float16",
+                        type_name(element_kind),
+                        output_index
+                    );
+                }
+                output = f32s_from_bytes(&output)
+                    .into_iter()
+                    .flat_map(|value| f16::from_f32(value).to_ne_bytes())
+                    .collect();
+            }
+            outputs.push(output);
+        }
+        Ok(outputs)
+    }
+
+    /// Describe the model's tensors at `indices`.
+    fn describe_tensors<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        model: &FlatBufferModel,
+        indices: &[TensorIndex],
+    ) -> anyhow::Result<Vec<TensorDescription>> {
+        let schema = unpack_model(model)?;
+        indices
+            .iter()
+            .map(|index| {
+                let info = tensor_info(interpreter, *index)?;
+                Ok(TensorDescription {
+                    index: *index,
+                    name: info.name,
+                    dims: info.dims,
+                    dtype: type_name(info.element_kind),
+                    quantization: quantization(&schema, *index),
+                })
+            })
+            .collect()
+    }
+
+    /// Describe every tensor of the interpreter and every node of the model's
+    /// main subgraph.
+    fn describe_graph<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        model: &FlatBufferModel,
+    ) -> anyhow::Result<GraphReport> {
+        let indices = (0..interpreter.tensors_size() as TensorIndex).collect::<Vec<_>>();
+        let tensors = self.describe_tensors(interpreter, model, &indices)?;
+        let schema = unpack_model(model)?;
+        let mut nodes = Vec::new();
+        if let Some(subgraph) = schema.subgraphs.first() {
+            for (index, operator) in subgraph.operators.iter().enumerate() {
+                let operator_code = schema
+                    .operator_codes
+                    .get(operator.opcode_index as usize)
+                    .ok_or_else(|| anyhow::anyhow!("invalid opcode index of node {}", index))?;
+                nodes.push(NodeDescription {
+                    index,
+                    op: operator_name(operator_code),
+                    inputs: operator.inputs.to_vec(),
+                    outputs: operator.outputs.to_vec(),
+                });
+            }
+        }
+        Ok(GraphReport { tensors, nodes })
+    }
+
+    /// Load the models of the pipeline stages.
+    fn load_pipeline(&self) -> anyhow::Result<Vec<Arc<FlatBufferModel>>> {
+        let stages = self.pipeline.iter().flatten();
+        stages
+            .map(|stage| self.load_model(&stage.model, stage.model_sha256.as_deref()))
+            .collect()
+    }
+
+    /// Build the interpreters of the pipeline stages, checking that the
+    /// inputs of each stage match the outputs of the previous one, starting
+    /// with `interpreter`. Stages run on `num_threads` threads unless they
+    /// override it.
+    fn build_pipeline<'a>(
+        &self,
+        interpreter: &Interpreter<&'a BuiltinOpResolver>,
+        models: impl IntoIterator<Item = &'a FlatBufferModel>,
+        resolver: &'a BuiltinOpResolver,
+        num_threads: c_int,
+        timing: &mut TimingReport,
+    ) -> anyhow::Result<Vec<StageInterpreter<'a, &'a BuiltinOpResolver>>> {
+        let mut stages: Vec<StageInterpreter<_>> = Vec::new();
+        let configs = self.pipeline.iter().flatten();
+        for (i, (model, config)) in models.into_iter().zip(configs).enumerate() {
+            let num_threads = config.num_threads.map_or(num_threads, |num_threads| {
+                self.resolve_num_threads(num_threads)
+            });
+            let model_name = format!("{} of pipeline stage {}", config.model, i);
+            let next = self.build_interpreter(model, &model_name, resolver, num_threads, timing)?;
+            let previous = stages
+                .last()
+                .map_or(interpreter, |stage| &stage.interpreter);
+            if previous.outputs().len() != next.inputs().len() {
+                return Err(anyhow::anyhow!(
+                    "pipeline stage {} takes {} input tensor(s) but the previous stage produces {}",
+                    i,
+                    next.inputs().len(),
+                    previous.outputs().len()
+                ))
+                .context(InferenceError::ShapeMismatch);
+            }
+            for (output_index, input_index) in previous.outputs().iter().zip(next.inputs()) {
+                let output = tensor_info(previous, *output_index)?;
+                let input = tensor_info(&next, *input_index)?;
+                if output.dims != input.dims || output.element_kind != input.element_kind {
+                    return Err(anyhow::anyhow!(
+                        "pipeline stage {} input tensor {} is {} {:?} but the previous stage \
+                         produces {} {:?}",
+                        i,
+                        input_index,
+                        type_name(input.element_kind),
+                        input.dims,
+                        type_name(output.element_kind),
+                        output.dims
+                    ))
+                    .context(InferenceError::ShapeMismatch);
+                }
+            }
+            stages.push(StageInterpreter {
+                model,
+                interpreter: next,
+            });
+        }
+        Ok(stages)
+    }
+
+    /// Feed the outputs of `interpreter` through the pipeline stages, and
+    /// return the last interpreter of the pipeline with its model.
+    fn run_pipeline<'i, 'a, Op: OpResolver>(
+        &self,
+        interpreter: &'i Interpreter<'a, Op>,
+        model: &'i FlatBufferModel,
+        pipeline: &'i mut [StageInterpreter<'a, Op>],
+        timing: &mut TimingReport,
+    ) -> anyhow::Result<(&'i Interpreter<'a, Op>, &'i FlatBufferModel)> {
+        let (mut previous, mut model) = (interpreter, model);
+        for stage in pipeline {
+            for (output_index, input_index) in previous
+                .outputs()
+                .iter()
+                .zip(stage.interpreter.inputs().to_vec())
+            {
+                let output = previous.tensor_buffer(*output_index).ok_or_else(|| {
+                    anyhow::anyhow!("invalid output tensor index {}", output_index)
+                })?;
+                stage
+                    .interpreter
+                    .tensor_buffer_mut(input_index)
+                    .ok_or_else(|| anyhow::anyhow!("invalid input tensor index {}", input_index))?
+                    .copy_from_slice(output);
+            }
+            let start = Instant::now();
+            self.invoke_once(&mut stage.interpreter)
+                .context(InferenceError::Invoke)?;
+            timing.invoke += elapsed_us(start);
+            (previous, model) = (&stage.interpreter, stage.model);
+        }
+        Ok((previous, model))
+    }
+
+    /// Compare the raw output tensors with the reference tensors at
+    /// `reference_output_paths`, within `tolerance`.
+    fn compare_outputs<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        reference_output_paths: &[PathBuf],
+    ) -> anyhow::Result<()> {
+        if reference_output_paths.len() != interpreter.outputs().len() {
+            anyhow::bail!(
+                "model {} produces {} output tensor(s) but {} reference path(s) were supplied",
+                self.main_model_name(),
+                interpreter.outputs().len(),
+                reference_output_paths.len()
+            );
+        }
+        let tolerance = self.tolerance.unwrap_or(0.0) as f64;
+        for (output_index, path) in interpreter.outputs().iter().zip(reference_output_paths) {
+            let info = tensor_info(interpreter, *output_index)?;
+            let output = interpreter
+                .tensor_buffer(*output_index)
+                .ok_or_else(|| anyhow::anyhow!("invalid output tensor index {}", output_index))?;
+            let path = self.root().join(path);
+            let reference =
+                fs::read(&path).with_context(|| format!("failed to read {:?}", path))?;
+            compare_output(output, &reference, info.element_kind, tolerance).with_context(
+                || format!("output tensor {} doesn't match {:?}", output_index, path),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Run inference on the inputs at `input_tensor_paths` with each of the
+    /// numbers of threads of `thread_sweep`, and report their latencies.
+    fn sweep_threads(
+        &self,
+        model: &FlatBufferModel,
+        resolver: &BuiltinOpResolver,
+        thread_sweep: &[c_int],
+        input_tensor_paths: &[PathBuf],
+    ) -> anyhow::Result<()> {
+        if let Some(path) = input_tensor_paths.iter().find(|path| path.is_dir()) {
+            return Err(anyhow::anyhow!(
+                "thread sweeps cannot read inputs from directory {:?}",
+                path
+            ))
+            .context(InferenceError::ConfigParse);
+        }
+        let raw_inputs = read_inputs(input_tensor_paths, self.max_input_bytes, self.input_wait_ms)?;
+        let model_name = self.main_model_name();
+        let mut report = Vec::new();
+        for num_threads in thread_sweep {
+            // Entries keep the requested number of threads, as in the sweep
+            let resolved_num_threads = self.resolve_num_threads(*num_threads);
+            info!(
+                "running inference with {} thread(s)...",
+                resolved_num_threads
+            );
+            let mut timing = TimingReport::default();
+            let mut interpreter = self.build_interpreter(
+                model,
+                &model_name,
+                resolver,
+                resolved_num_threads,
+                &mut timing,
+            )?;
+            let inputs = match self.npy_io {
+                true => self
+                    .strip_npy_headers(&interpreter, &raw_inputs)
+                    .context(InferenceError::ShapeMismatch)?,
+                false => raw_inputs.clone(),
+            };
+            self.feed_inputs(&mut interpreter, model, &inputs)
+                .context(InferenceError::ShapeMismatch)?;
+            for _ in 0..self.warmup_runs.unwrap_or(0) {
+                self.invoke_once(&mut interpreter)
+                    .context(InferenceError::Invoke)?;
+            }
+            let mut durations = Vec::new();
+            for _ in 0..self.benchmark_runs.unwrap_or(1).max(1) {
+                let start = Instant::now();
+                self.invoke_once(&mut interpreter)
+                    .context(InferenceError::Invoke)?;
+                durations.push(elapsed_us(start));
+            }
+            report.push(ThreadSweepEntry {
+                num_threads: *num_threads,
+                latency: BenchmarkReport::new(durations),
+            });
+        }
+        match &self.thread_sweep_report_path {
+            Some(thread_sweep_report_path) => {
+                self.write_report(thread_sweep_report_path, &report)?;
+            }
+            None => println!("{}", serde_json::to_string(&report)?),
+        }
+        Ok(())
+    }
+
+    /// Encode `values` as the data of the model's single input tensor,
+    /// converted to its type and quantized if needed, see `inline_input`.
+    fn encode_inline_input<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        model: &FlatBufferModel,
+        values: &[f32],
+    ) -> anyhow::Result<Vec<u8>> {
+        let input_index = match interpreter.inputs() {
+            [input_index] => *input_index,
+            inputs => anyhow::bail!(
+                "inline inputs require a single input tensor, not {}",
+                inputs.len()
+            ),
+        };
+        let info = tensor_info(interpreter, input_index)?;
+        let elements = info.dims.iter().product::<usize>();
+        if values.len() != elements {
+            anyhow::bail!(
+                "input tensor {} expects {} elements but {} inline values were supplied",
+                input_index,
+                elements,
+                values.len()
+            );
+        }
+        let quantization = quantization(&unpack_model(model)?, input_index);
+        encode_f32s(values, info.element_kind, quantization.as_ref())
+    }
+
+    /// Read the input tensors from the blob at `input_blob_path`, checking
+    /// that they match the model's inputs.
+    fn read_blob<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        input_blob_path: &Path,
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let input_blob_path = self.root().join(input_blob_path);
+        wait_for(&input_blob_path, self.input_wait_ms).context(InferenceError::InputIo)?;
+        let blob = read_file(&input_blob_path, self.max_input_bytes)
+            .with_context(|| format!("failed to read {:?}", input_blob_path))
+            .context(InferenceError::InputIo)?;
+        let inputs = parse_blob(&blob)
+            .with_context(|| format!("invalid input blob {:?}", input_blob_path))
+            .context(InferenceError::InputIo)?;
+        if inputs.len() != interpreter.inputs().len() {
+            return Err(anyhow::anyhow!(
+                "model {} expects {} input tensor(s) but the input blob holds {}",
+                self.main_model_name(),
+                interpreter.inputs().len(),
+                inputs.len()
+            ))
+            .context(InferenceError::ShapeMismatch);
+        }
+        // Converted or `.npy` inputs are checked once decoded
+        if self.input_dtype.is_none() && !self.npy_io {
+            for (i, (input, input_index)) in inputs.iter().zip(interpreter.inputs()).enumerate() {
+                let tensor_len = interpreter
+                    .tensor_buffer(*input_index)
+                    .map_or(0, <[u8]>::len);
+                if input.len() != tensor_len {
+                    return Err(anyhow::anyhow!(
+                        "input blob segment {} is {} bytes but input tensor {} is {} bytes",
+                        i,
+                        input.len(),
+                        input_index,
+                        tensor_len
+                    ))
+                    .context(InferenceError::ShapeMismatch);
+                }
+            }
+        }
+        Ok(inputs)
+    }
+
+    /// Answer the inference requests received on the Unix domain socket at
+    /// `socket_path`.
+    #[cfg(unix)]
+    fn serve<'a, Op: OpResolver>(
+        &self,
+        interpreter: &mut Interpreter<'a, Op>,
+        model: &FlatBufferModel,
+        pipeline: &mut [StageInterpreter<'a, Op>],
+        socket_path: &Path,
+    ) -> anyhow::Result<()> {
+        let mut timing = TimingReport::default();
+        let limits = server::RequestLimits {
+            max_tensors: interpreter.inputs().len(),
+            max_tensor_bytes: self.max_input_bytes,
+        };
+        server::serve(socket_path, limits, |inputs| {
+            self.feed_inputs(interpreter, model, inputs)
+                .context(InferenceError::ShapeMismatch)?;
+            self.invoke(interpreter, &mut timing)?;
+            let (interpreter, model) =
+                self.run_pipeline(interpreter, model, pipeline, &mut timing)?;
+            self.collect_outputs(interpreter, model)
+                .map_err(|err| classify(err, InferenceError::Invoke))
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn serve<'a, Op: OpResolver>(
+        &self,
+        _: &mut Interpreter<'a, Op>,
+        _: &FlatBufferModel,
+        _: &mut [StageInterpreter<'a, Op>],
+        socket_path: &Path,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "cannot serve on {:?}: Unix domain sockets are not supported",
+            socket_path
+        )
+    }
+
+    /// Write the data of the tensors at `indices` to `debug_dir/<index>.bin`.
+    fn dump_tensors<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+        indices: &[usize],
+        debug_dir: &Path,
+    ) -> anyhow::Result<()> {
+        fs::create_dir_all(debug_dir)
+            .with_context(|| format!("failed to create {:?}", debug_dir))?;
+        for index in indices {
+            // Tensors optimized away by the converter or the interpreter
+            // either don't exist or hold no data
+            let data = TensorIndex::try_from(*index)
+                .ok()
+                .filter(|_| *index < interpreter.tensors_size())
+                .and_then(|index| interpreter.tensor_buffer(index))
+                .filter(|data| !data.is_empty())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("tensor {} doesn't exist or holds no data", index)
+                })?;
+            let path = debug_dir.join(format!("{}.bin", index));
+            self.create_file(&path)
+                .and_then(|mut file| file.write_all(data))
+                .with_context(|| format!("failed to write {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    /// Operator resolver selected by `resolver`.
+    fn op_resolver(&self) -> BuiltinOpResolver {
+        // The TensorFlow Lite version built by the `tflite` bindings predates
+        // default delegates, so the builtin resolver never applies any
+        match self.resolver.unwrap_or_default() {
+            ResolverKind::Builtin | ResolverKind::BuiltinWithoutDefaultDelegates => {
+                BuiltinOpResolver::default()
+            }
+        }
+    }
+
+    /// Byte order of the elements of the raw output files, according to
+    /// `output_endianness` and `canonical_output_endianness`.
+    fn output_byte_order(&self) -> Option<Endianness> {
+        let canonical = self
+            .canonical_output_endianness
+            .then_some(Endianness::Little);
+        self.output_endianness.or(canonical)
+    }
+
+    /// Indices of the output tensors of `interpreter` selected by
+    /// `output_indices`, checking that they are in range.
+    fn selected_outputs<Op: OpResolver>(
+        &self,
+        interpreter: &Interpreter<Op>,
+    ) -> anyhow::Result<Vec<TensorIndex>> {
+        let outputs = interpreter.outputs();
+        match &self.output_indices {
+            Some(output_indices) => output_indices
+                .iter()
+                .map(|i| {
+                    outputs.get(*i).copied().ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "output index {} is out of range, the model has {} output tensor(s)",
+                            i,
+                            outputs.len()
+                        )
+                    })
+                })
+                .collect::<anyhow::Result<_>>()
+                .context(InferenceError::ConfigParse),
+            None => Ok(outputs.to_vec()),
+        }
+    }
+
+    /// Element type of the output files of tensors of type `element_kind`,
+    /// once converted according to `output_as_float16` and
+    /// `dequantize_output`.
+    fn output_element_kind(&self, element_kind: ElementKind) -> ElementKind {
+        match (self.output_as_float16, self.dequantize_output) {
+            (true, _) => ElementKind::kTfLiteFloat16,
+            (false, true) if is_dequantized(element_kind) => ElementKind::kTfLiteFloat32,
+            (false, _) => element_kind,
+        }
+    }
+
+    /// Make sure the parent directory of the output file `path` exists,
+    /// creating it if `create_output_dirs` is set.
+    fn create_parent_dir(&self, path: &Path) -> anyhow::Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !parent.is_dir() => {
+                if !self.create_output_dirs {
+                    return Err(anyhow::anyhow!(
+                        "output directory {:?} doesn't exist",
+                        parent
+                    ))
+                    .context(InferenceError::OutputIo);
+                }
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("failed to create {:?}", parent))
+                    .context(InferenceError::OutputIo)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run inference on the files at `input_tensor_paths`, or on each of
+    /// their files in turn if they are directories.
+    fn infer_run<'a, Op: OpResolver>(
+        &self,
+        interpreter: &mut Interpreter<'a, Op>,
+        model: &FlatBufferModel,
+        pipeline: &mut [StageInterpreter<'a, Op>],
+        input_tensor_paths: &[PathBuf],
+        output_tensor_paths: &[PathBuf],
+        timing: &mut TimingReport,
+    ) -> anyhow::Result<()> {
+        // Inputs that are all directories are processed as a batch, running
+        // inference on each of their files in turn, sorted by name, and
+        // writing the outputs under the same names in the output directories,
+        // unless they are appended to single output files
+        let batch = !input_tensor_paths.is_empty() && input_tensor_paths.iter().all(|p| p.is_dir());
+        if batch && self.output_append {
+            if let Some(path) = output_tensor_paths.iter().find(|path| path.is_dir()) {
+                return Err(anyhow::anyhow!(
+                    "outputs are appended to but output {:?} is a directory",
+                    path
+                ))
+                .context(InferenceError::ConfigParse);
+            }
+        } else if batch {
+            if let Some(path) = output_tensor_paths.iter().find(|path| path.is_file()) {
+                return Err(anyhow::anyhow!(
+                    "inputs are directories but output {:?} is a file",
+                    path
+                ))
+                .context(InferenceError::ConfigParse);
+            }
+            // The output directories themselves are always created, but not
+            // their parents unless `create_output_dirs` is set
+            for output_tensor_path in output_tensor_paths {
+                if !output_tensor_path.is_dir() {
+                    self.create_parent_dir(output_tensor_path)?;
+                    fs::create_dir(output_tensor_path)
+                        .with_context(|| format!("failed to create {:?}", output_tensor_path))
+                        .context(InferenceError::OutputIo)?;
+                }
+            }
+        }
+        if batch {
+            let input_dir = &input_tensor_paths[0];
+            let list_files = || -> io::Result<Vec<OsString>> {
+                let mut names = Vec::new();
+                for entry in fs::read_dir(input_dir)? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_file() {
+                        names.push(entry.file_name());
+                    }
+                }
+                Ok(names)
+            };
+            let mut names = list_files()
+                .with_context(|| format!("failed to list {:?}", input_dir))
+                .context(InferenceError::InputIo)?;
+            names.sort();
+            if self.batch_workers.is_some_and(|workers| workers > 1) {
+                let stage_models = pipeline.iter().map(|stage| stage.model).collect::<Vec<_>>();
+                return self.infer_batch_parallel(
+                    model,
+                    &stage_models,
+                    &names,
+                    input_tensor_paths,
+                    output_tensor_paths,
+                    timing,
+                );
+            }
+            for name in names {
+                info!("running inference on {:?}...", name);
+                let inputs = input_tensor_paths.iter().map(|path| path.join(&name));
+                let outputs = output_tensor_paths
+                    .iter()
+                    .map(|path| match self.output_append {
+                        true => path.clone(),
+                        false => path.join(&name),
+                    });
+                self.infer_files(
+                    interpreter,
+                    model,
+                    pipeline,
+                    &inputs.collect::<Vec<_>>(),
+                    &outputs.collect::<Vec<_>>(),
+                    timing,
+                )?;
+            }
+        } else {
+            if let Some(path) = input_tensor_paths.iter().find(|path| path.is_dir()) {
+                return Err(anyhow::anyhow!(
+                    "input {:?} is a directory but not all inputs are",
+                    path
+                ))
+                .context(InferenceError::ConfigParse);
+            }
+            if let Some(path) = output_tensor_paths.iter().find(|path| path.is_dir()) {
+                return Err(anyhow::anyhow!(
+                    "inputs are files but output {:?} is a directory",
+                    path
+                ))
+                .context(InferenceError::ConfigParse);
+            }
+            self.infer_files(
+                interpreter,
+                model,
+                pipeline,
+                input_tensor_paths,
+                output_tensor_paths,
+                timing,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Run inference on the files `names` of the input directories
+    /// `input_tensor_paths` with `batch_workers` interpreters in parallel,
+    /// each taking the next file in turn, and report the throughput.
+    /// `stage_models` are the models of the pipeline stages.
+    fn infer_batch_parallel(
+        &self,
+        model: &FlatBufferModel,
+        stage_models: &[&FlatBufferModel],
+        names: &[OsString],
+        input_tensor_paths: &[PathBuf],
+        output_tensor_paths: &[PathBuf],
+        timing: &mut TimingReport,
+    ) -> anyhow::Result<()> {
+        let workers = self.batch_workers.unwrap_or(1);
+        let next = &AtomicUsize::new(0);
+        // The other workers stop taking files once one of them fails
+        let failed = &AtomicBool::new(false);
+        let model_name = &self.main_model_name();
+        let worker = move || -> anyhow::Result<TimingReport> {
+            let mut timing = TimingReport::default();
+            let resolver = self.op_resolver();
+            let num_threads = self.resolved_num_threads();
+            let mut interpreter =
+                self.build_interpreter(model, model_name, &resolver, num_threads, &mut timing)?;
+            let mut pipeline = self.build_pipeline(
+                &interpreter,
+                stage_models.iter().copied(),
+                &resolver,
+                num_threads,
+                &mut timing,
+            )?;
+            while !failed.load(Ordering::SeqCst) {
+                let name = match names.get(next.fetch_add(1, Ordering::SeqCst)) {
+                    Some(name) => name,
+                    None => break,
+                };
+                info!("running inference on {:?}...", name);
+                let inputs = input_tensor_paths.iter().map(|path| path.join(name));
+                let outputs = output_tensor_paths.iter().map(|path| path.join(name));
+                let result = self.infer_files(
+                    &mut interpreter,
+                    model,
+                    &mut pipeline,
+                    &inputs.collect::<Vec<_>>(),
+                    &outputs.collect::<Vec<_>>(),
+                    &mut timing,
+                );
+                if result.is_err() {
+                    failed.store(true, Ordering::SeqCst);
+                    result?;
+                }
+            }
+            Ok(timing)
+        };
+
+        let start = Instant::now();
+        let results = thread::scope(|scope| {
+            let handles = (0..workers)
+                .map(|_| scope.spawn(worker))
+                .collect::<Vec<_>>();
+            let results = handles.into_iter().map(|handle| handle.join());
+            results.collect::<Vec<_>>()
+        });
+        for result in results {
+            let worker_timing = result.unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+            timing.add(&worker_timing);
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        info!(
+            "processed {} file(s) with {} workers in {:.3}s, {:.1} files/s",
+            names.len(),
+            workers,
+            elapsed,
+            names.len() as f64 / elapsed
+        );
+        Ok(())
+    }
+
+    /// Run inference on the input files at `input_tensor_paths`, writing the
+    /// outputs to `output_tensor_paths` unless in benchmark mode.
+    fn infer_files<'a, Op: OpResolver>(
+        &self,
+        interpreter: &mut Interpreter<'a, Op>,
+        model: &FlatBufferModel,
+        pipeline: &mut [StageInterpreter<'a, Op>],
+        input_tensor_paths: &[PathBuf],
+        output_tensor_paths: &[PathBuf],
+        timing: &mut TimingReport,
+    ) -> anyhow::Result<()> {
+        // Load and configure inputs.
+        // Input paths are matched with the model's inputs in order
+        let start = Instant::now();
+        let mut inputs = match &self.input_image_path {
+            Some(input_image_path) => {
+                let input_image_path = self.root().join(input_image_path);
+                wait_for(&input_image_path, self.input_wait_ms).context(InferenceError::InputIo)?;
+                vec![
+                    read_image(interpreter, &input_image_path, self.max_input_bytes)
+                        .context(InferenceError::InputIo)?,
+                ]
+            }
+            None if self.zero_fill_input => {
+                let mut inputs = Vec::new();
+                for input_index in interpreter.inputs() {
+                    let input = interpreter.tensor_buffer(*input_index).ok_or_else(|| {
+                        anyhow::anyhow!("invalid input tensor index {}", input_index)
+                    })?;
+                    inputs.push(vec![0; input.len()]);
+                }
+                inputs
+            }
+            None => match (&self.inline_input, &self.input_blob_path) {
+                (Some(inline_input), _) => vec![self
+                    .encode_inline_input(interpreter, model, inline_input)
+                    .context(InferenceError::ShapeMismatch)?],
+                (None, Some(input_blob_path)) => self.read_blob(interpreter, input_blob_path)?,
+                (None, None) => {
+                    read_inputs(input_tensor_paths, self.max_input_bytes, self.input_wait_ms)?
+                }
+            },
+        };
+        if self
+            .input_endianness
+            .is_some_and(|endianness| !endianness.is_native())
+        {
+            for (input, input_index) in inputs.iter_mut().zip(interpreter.inputs()) {
+                let element_kind = tensor_info(interpreter, *input_index)?.element_kind;
+                let input_kind = self.input_dtype.map_or(element_kind, DType::element_kind);
+                let size = element_size(input_kind)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("cannot swap the bytes of {} inputs", type_name(input_kind))
+                    })
+                    .context(InferenceError::ShapeMismatch)?;
+                swap_bytes(input, size);
+            }
+        }
+        let inputs = match self.npy_io {
+            true => self
+                .strip_npy_headers(interpreter, &inputs)
+                .context(InferenceError::ShapeMismatch)?,
+            false => inputs,
+        };
+        let mut chunks = match self.max_batch_chunk {
+            Some(max_batch_chunk) => self
+                .batch_chunks(interpreter, max_batch_chunk, inputs)
+                .context(InferenceError::ShapeMismatch)?,
+            None => vec![inputs],
+        };
+        let last_chunk = chunks.pop().unwrap_or_default();
+        timing.input_read += elapsed_us(start);
+
+        // All the chunks but the last are only collected, the last one
+        // going through the same steps as unsplit inputs
+        let mut chunk_outputs = Vec::<Vec<u8>>::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            info!("running inference on batch chunk {}...", i);
+            self.feed_inputs(interpreter, model, chunk)
+                .context(InferenceError::ShapeMismatch)?;
+            self.invoke(interpreter, timing)?;
+            let outputs = self
+                .collect_outputs(interpreter, model)
+                .map_err(|err| classify(err, InferenceError::Invoke))?;
+            chunk_outputs.resize(outputs.len(), Vec::new());
+            for (chunk_output, output) in chunk_outputs.iter_mut().zip(outputs) {
+                chunk_output.extend(output);
+            }
+        }
+        self.feed_inputs(interpreter, model, &last_chunk)
+            .context(InferenceError::ShapeMismatch)?;
+        self.invoke(interpreter, timing)?;
+        if let (Some(debug_tensors), Some(debug_dir)) = (&self.debug_tensors, &self.debug_dir) {
+            self.dump_tensors(interpreter, debug_tensors, &self.root().join(debug_dir))
+                .context(InferenceError::OutputIo)?;
+        }
+        let (interpreter, model) = self.run_pipeline(interpreter, model, pipeline, timing)?;
+
+        let stats = match self.output_stats {
+            true => output_stats(interpreter, &self.selected_outputs(interpreter)?)
+                .context(InferenceError::OutputIo)?,
+            false => Vec::new(),
+        };
+        if let Some(reference_output_paths) = &self.reference_output_paths {
+            self.compare_outputs(interpreter, reference_output_paths)
+                .context(InferenceError::OutputMismatch)?;
+        }
+
+        // Get outputs.
+        // Output paths are matched with the model's outputs in order
+        let writes_outputs = self.benchmark_runs.is_none() || self.benchmark_write_outputs;
+        if writes_outputs {
+            let output_indices = self.selected_outputs(interpreter)?;
+            let mut outputs = self
+                .collect_outputs(interpreter, model)
+                .map_err(|err| classify(err, InferenceError::Invoke))?;
+            for (output, mut chunk_output) in outputs.iter_mut().zip(chunk_outputs) {
+                chunk_output.append(output);
+                *output = chunk_output;
+            }
+            if self.concat_outputs && output_tensor_paths.len() != 1 {
+                return Err(anyhow::anyhow!(
+                    "concatenated outputs are written to a single path but {} output path(s) \
+                     were supplied",
+                    output_tensor_paths.len()
+                ))
+                .context(InferenceError::ConfigParse);
+            } else if !self.concat_outputs && outputs.len() != output_tensor_paths.len() {
+                return Err(anyhow::anyhow!(
+                    "model {} writes {} output tensor(s) but {} output path(s) were supplied",
+                    self.main_model_name(),
+                    outputs.len(),
+                    output_tensor_paths.len()
+                ))
+                .context(InferenceError::ConfigParse);
+            }
+
+            // `npy_io` implies `.npy` outputs unless another format is set
+            let output_format = match self.output_format {
+                OutputFormat::Raw if self.npy_io => OutputFormat::Npy,
+                output_format => output_format,
+            };
+            if output_format != OutputFormat::Raw || self.text_output {
+                for (output, output_index) in outputs.iter_mut().zip(&output_indices) {
+                    let info = tensor_info(interpreter, *output_index)?;
+                    let element_kind = self.output_element_kind(info.element_kind);
+                    *output = match output_format {
+                        // Only reached with `text_output` set
+                        OutputFormat::Raw => {
+                            let elements = format_elements(output, element_kind)
+                                .context(InferenceError::Invoke)?;
+                            let text = elements.iter().map(|element| element.clone() + "\n");
+                            text.collect::<String>().into_bytes()
+                        }
+                        OutputFormat::Csv => {
+                            let elements = format_elements(output, element_kind)
+                                .context(InferenceError::Invoke)?;
+                            let csv = format_csv(&elements, &info.dims)
+                                .with_context(|| {
+                                    format!("cannot write output tensor {} as CSV", output_index)
+                                })
+                                .context(InferenceError::Invoke)?;
+                            csv.into_bytes()
+                        }
+                        OutputFormat::Json => {
+                            let elements = format_elements(output, element_kind)
+                                .context(InferenceError::Invoke)?;
+                            format_json(&elements, &info.dims).into_bytes()
+                        }
+                        OutputFormat::Npy => npy::encode(output, element_kind, &info.dims)
+                            .context(InferenceError::Invoke)?,
+                    };
+                }
+            }
+
+            if self
+                .output_byte_order()
+                .is_some_and(|endianness| !endianness.is_native())
+            {
+                for (output, output_index) in outputs.iter_mut().zip(&output_indices) {
+                    let element_kind = tensor_info(interpreter, *output_index)?.element_kind;
+                    let element_kind = self.output_element_kind(element_kind);
+                    let size = element_size(element_kind)
+                        .ok_or_else(|| {
+                            let kind = type_name(element_kind);
+                            anyhow::anyhow!("cannot swap the bytes of {} outputs", kind)
+                        })
+                        .context(InferenceError::Invoke)?;
+                    swap_bytes(output, size);
+                }
+            }
+
+            if self.concat_outputs {
+                let mut entries = Vec::new();
+                for (output, output_index) in outputs.iter().zip(&output_indices) {
+                    let info = tensor_info(interpreter, *output_index)?;
+                    entries.push(ConcatEntry {
+                        length: output.len(),
+                        dims: info.dims,
+                        dtype: type_name(self.output_element_kind(info.element_kind)),
+                    });
+                }
+                outputs = vec![concat_tensors(&outputs, &entries).context(InferenceError::Invoke)?];
+            }
+
+            if let Some(verify_output_sha256) = &self.verify_output_sha256 {
+                verify_sha256(&outputs.concat(), verify_output_sha256)
+                    .context("the outputs are not the expected ones")
+                    .context(InferenceError::OutputMismatch)?;
+            }
+
+            info!("writing results...");
+            let start = Instant::now();
+            for (output, output_tensor_path) in outputs.iter().zip(output_tensor_paths.iter()) {
+                let result = if output_tensor_path == Path::new(STDIO_PATH) {
+                    io::stdout()
+                        .write_all(output)
+                        .and_then(|_| io::stdout().flush())
+                } else {
+                    self.create_parent_dir(output_tensor_path)?;
+                    open_output(
+                        output_tensor_path,
+                        self.output_append,
+                        self.overwrite_output,
+                        self.output_mode,
+                    )
+                    .and_then(|mut file| file.write_all(output))
+                };
+                result
+                    .with_context(|| format!("failed to write {:?}", output_tensor_path))
+                    .context(InferenceError::OutputIo)?;
+            }
+            timing.output_write += elapsed_us(start);
+        }
+
+        for (i, stats) in stats.iter().enumerate() {
+            let stats = match stats {
+                Some(stats) => stats,
+                None => continue,
+            };
+            match output_tensor_paths.get(i) {
+                Some(path) if writes_outputs && path != Path::new(STDIO_PATH) => {
+                    let mut stats_path = path.clone().into_os_string();
+                    stats_path.push(".stats.json");
+                    self.create_file(Path::new(&stats_path))
+                        .map_err(anyhow::Error::from)
+                        .and_then(|file| Ok(serde_json::to_writer(file, stats)?))
+                        .with_context(|| format!("failed to write {:?}", stats_path))
+                        .context(InferenceError::OutputIo)?;
+                }
+                _ => info!(
+                    "output tensor statistics: {}",
+                    serde_json::to_string(stats)?
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The core service. It loads the model described by `model` then feeds
+    /// the inputs read from `input_tensor_paths` to the model, and writes the
+    /// resulting tensors to the files at `output_tensor_paths`.
+    /// The interpreter can be further configured with `num_threads`.
+    /// If `timing_report_path` is set, the duration of each phase is written
+    /// there as JSON.
+    fn infer(&self, summary: &mut Summary) -> anyhow::Result<()> {
+        let root = self.root();
+        let mut timing = TimingReport::default();
+        let rss_before = match self.report_peak_rss {
+            true => peak_rss()?,
+            false => None,
+        };
+
+        let start = Instant::now();
+        let (model, model_digest) = self.load_main_model()?;
+        timing.model_load = elapsed_us(start);
+        let resolver = self.op_resolver();
+        if let Some(model_digest) = model_digest {
+            summary.model_sha256 = hex::encode(model_digest);
+        }
+        let num_threads = self.resolved_num_threads();
+        let model_name = self.main_model_name();
+        let mut interpreter =
+            self.build_interpreter(&model, &model_name, &resolver, num_threads, &mut timing)?;
+        summary.num_threads = num_threads;
+        let start = Instant::now();
+        let pipeline_models = self.load_pipeline()?;
+        timing.model_load += elapsed_us(start);
+        let mut pipeline = self.build_pipeline(
+            &interpreter,
+            pipeline_models.iter().map(Arc::as_ref),
+            &resolver,
+            num_threads,
+            &mut timing,
+        )?;
+        let last = pipeline
+            .last()
+            .map_or(&interpreter, |stage| &stage.interpreter);
+        for (shapes, interpreter, indices) in [
+            (
+                &mut summary.input_shapes,
+                &interpreter,
+                interpreter.inputs(),
+            ),
+            (&mut summary.output_shapes, last, last.outputs()),
+        ] {
+            for index in indices {
+                shapes.push(tensor_info(interpreter, *index)?.dims);
+            }
+        }
+
+        if let Some(metadata_path) = &self.metadata_path {
+            self.write_report(metadata_path, &model_metadata(&model)?)?;
+        }
+
+        if let Some(memory_report_path) = &self.memory_report_path {
+            self.write_report(memory_report_path, &memory_report(&interpreter)?)?;
+        }
+
+        if self.print_graph {
+            let report = self.describe_graph(&interpreter, &model)?;
+            match &self.graph_report_path {
+                Some(graph_report_path) => {
+                    self.write_report(graph_report_path, &report)?;
+                }
+                None => println!("{}", serde_json::to_string(&report)?),
+            }
+        }
+
+        if self.dry_run {
+            let report = DryRunReport {
+                inputs: self.describe_tensors(&interpreter, &model, interpreter.inputs())?,
+                outputs: self.describe_tensors(&interpreter, &model, interpreter.outputs())?,
+            };
+            match &self.dry_run_report_path {
+                Some(dry_run_report_path) => {
+                    self.write_report(dry_run_report_path, &report)?;
+                }
+                None => println!("{}", serde_json::to_string(&report)?),
+            }
+            return Ok(());
+        }
+
+        if let Some(socket_path) = &self.socket_path {
+            return self.serve(
+                &mut interpreter,
+                &model,
+                &mut pipeline,
+                &root.join(socket_path),
+            );
+        }
+
+        if let Some(thread_sweep) = &self.thread_sweep {
+            let input_tensor_paths = match &self.runs {
+                Some(runs) => runs.first().map_or(&[][..], |run| &run.input_tensor_paths),
+                None => &self.input_tensor_paths,
+            };
+            let input_tensor_paths = resolve_paths(
+                &interpreter,
+                interpreter.inputs(),
+                input_tensor_paths,
+                &self.named_input_tensor_paths,
+            )
+            .context(InferenceError::ConfigParse)?
+            .iter()
+            .map(|input_tensor_path| resolve_stdio_path(root, input_tensor_path))
+            .collect::<Vec<_>>();
+            return self.sweep_threads(&model, &resolver, thread_sweep, &input_tensor_paths);
+        }
+
+        // Models without inputs, e.g. generators, simply read no file
+        if interpreter.inputs().is_empty() {
+            info!("model has no input tensors, skipping input loading");
+        }
+        let writes_outputs = self.benchmark_runs.is_none() || self.benchmark_write_outputs;
+        let last = pipeline
+            .last()
+            .map_or(&interpreter, |stage| &stage.interpreter);
+        if writes_outputs && last.outputs().is_empty() {
+            return Err(anyhow::anyhow!(
+                "model {} has no output tensors, nothing to write",
+                self.main_model_name()
+            ))
+            .context(InferenceError::ConfigParse);
+        }
+
+        // Each run has its own paths, the top-level ones being used if no
+        // runs are given
+        let runs = match &self.runs {
+            Some(runs) => runs
+                .iter()
+                .map(|run| {
+                    (
+                        &run.input_tensor_paths,
+                        &None,
+                        &run.output_tensor_paths,
+                        &None,
+                    )
+                })
+                .collect(),
+            None => vec![(
+                &self.input_tensor_paths,
+                &self.named_input_tensor_paths,
+                &self.output_tensor_paths,
+                &self.named_output_tensor_paths,
+            )],
+        };
+        for (input_paths, named_input_paths, output_paths, named_output_paths) in runs {
+            let input_tensor_paths = resolve_paths(
+                &interpreter,
+                interpreter.inputs(),
+                input_paths,
+                named_input_paths,
+            )
+            .context(InferenceError::ConfigParse)?
+            .iter()
+            .map(|input_tensor_path| resolve_stdio_path(root, input_tensor_path))
+            .collect::<Vec<_>>();
+            let last = pipeline
+                .last()
+                .map_or(&interpreter, |stage| &stage.interpreter);
+            let output_tensor_paths = if writes_outputs {
+                resolve_paths(
+                    last,
+                    &self.selected_outputs(last)?,
+                    output_paths,
+                    named_output_paths,
+                )
+                .context(InferenceError::ConfigParse)?
+                .iter()
+                .map(|output_tensor_path| resolve_stdio_path(root, output_tensor_path))
+                .collect()
+            } else {
+                Vec::new()
+            };
+            self.infer_run(
+                &mut interpreter,
+                &model,
+                &mut pipeline,
+                &input_tensor_paths,
+                &output_tensor_paths,
+                &mut timing,
+            )?;
+        }
+
+        if self.report_peak_rss {
+            match rss_before.zip(peak_rss()?) {
+                Some((before, after)) => {
+                    let report = PeakRssReport { before, after };
+                    match &self.peak_rss_report_path {
+                        Some(peak_rss_report_path) => {
+                            self.write_report(peak_rss_report_path, &report)?;
+                        }
+                        None => println!("{}", serde_json::to_string(&report)?),
+                    }
+                }
+                None => warn!("peak RSS reports are only supported on Linux, not reporting"),
+            }
+        }
+
+        // Outputs are those of the last stage of the pipeline, if any
+        let (last, last_model) = match pipeline.last() {
+            Some(stage) => (&stage.interpreter, stage.model),
+            None => (&interpreter, &*model),
+        };
+        if let Some(output_shape_path) = &self.output_shape_path {
+            let descriptions = self.describe_tensors(last, last_model, last.outputs())?;
+            self.write_report(output_shape_path, &descriptions)?;
+        }
+        if let Some(output_quant_path) = &self.output_quant_path {
+            let schema = unpack_model(last_model)?;
+            let quantizations = last
+                .outputs()
+                .iter()
+                .map(|index| quantization(&schema, *index));
+            self.write_report(output_quant_path, &quantizations.collect::<Vec<_>>())?;
+        }
+
+        summary.invoke = timing.invoke;
+        if let Some(timing_report_path) = &self.timing_report_path {
+            self.write_report(timing_report_path, &timing)?;
+        }
+
+        Ok(())
+    }
+
+    /// In-memory variant of the service: feeds `inputs` to the model, in the
+    /// order of the model's inputs, and returns the resulting output tensors.
+    /// The configured input and output paths and reports are ignored.
+    pub fn infer_bytes(&self, inputs: &[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>> {
+        self.validate().context(InferenceError::ConfigParse)?;
+        let mut timing = TimingReport::default();
+        let (model, _) = self.load_main_model()?;
+        let resolver = self.op_resolver();
+        let num_threads = self.resolved_num_threads();
+        let model_name = self.main_model_name();
+        let interpreter = &mut self.build_interpreter(
+            &model,
+            &model_name,
+            &resolver,
+            num_threads,
+            &mut timing,
+        )?;
+        let pipeline_models = self.load_pipeline()?;
+        let pipeline = &mut self.build_pipeline(
+            interpreter,
+            pipeline_models.iter().map(Arc::as_ref),
+            &resolver,
+            num_threads,
+            &mut timing,
+        )?;
+        self.feed_inputs(interpreter, &model, inputs)
+            .context(InferenceError::ShapeMismatch)?;
+        self.invoke(interpreter, &mut timing)?;
+        let (interpreter, model) = self.run_pipeline(interpreter, &model, pipeline, &mut timing)?;
+        self.collect_outputs(interpreter, model)
+            .map_err(|err| classify(err, InferenceError::Invoke))
+    }
+}
+
+impl Default for TfLiteInferenceService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run the inference described by `config`, reading its inputs from and
+/// writing its outputs to the configured paths.
+/// Errors carry an [`InferenceError`] context identifying the failing stage.
+pub fn run_inference(config: &TfLiteInferenceService) -> anyhow::Result<()> {
+    let mut summary = Summary {
+        model: config.model.to_string(),
+        ..Default::default()
+    };
+    let result = config
+        .validate()
+        .context(InferenceError::ConfigParse)
+        .and_then(|()| config.infer(&mut summary))
+        .map_err(|err| classify(err, InferenceError::Other));
+
+    if let Some(summary_path) = &config.summary_path {
+        summary.success = result.is_ok();
+        summary.failed_stage = result.as_ref().err().and_then(|err| {
+            err.downcast_ref::<InferenceError>()
+                .copied()
+                .map(InferenceError::stage)
+        });
+        let written = config
+            .create_file(&config.root().join(summary_path))
+            .map_err(anyhow::Error::from)
+            .and_then(|file| Ok(serde_json::to_writer(file, &summary)?))
+            .with_context(|| format!("failed to write the summary to {:?}", summary_path))
+            .context(InferenceError::OutputIo);
+        match (&result, written) {
+            (Ok(()), Err(err)) => return Err(err),
+            (Err(_), Err(err)) => warn!("{:?}", err),
+            (_, Ok(())) => (),
+        }
+    }
+    result
+}
+
+/// Describe the input and output tensors of the model at `model_path`, with
+/// their names, shapes, element types and quantization parameters, as JSON.
+/// Relative paths are resolved against the working directory.
+pub fn model_info(model_path: &Path) -> anyhow::Result<String> {
+    let mut service = TfLiteInferenceService::new();
+    service.model = ModelSource::Path(model_path.to_path_buf());
+    service.output_root = Some(PathBuf::from("."));
+    let (model, _) = service.load_main_model()?;
+    let resolver = service.op_resolver();
+    let num_threads = service.resolved_num_threads();
+    let mut timing = TimingReport::default();
+    let model_name = service.main_model_name();
+    let interpreter =
+        service.build_interpreter(&model, &model_name, &resolver, num_threads, &mut timing)?;
+    let report = DryRunReport {
+        inputs: service.describe_tensors(&interpreter, &model, interpreter.inputs())?,
+        outputs: service.describe_tensors(&interpreter, &model, interpreter.outputs())?,
+    };
+    Ok(serde_json::to_string_pretty(&report)?)
+}
+
+/// Describe the layout of the execution configuration, as expected by
+/// postcard, as JSON: the fields of each structure, in order, and the
+/// variants of each enumeration, keyed by type name.
+pub fn config_schema() -> anyhow::Result<String> {
+    // Tracing errors hold formats which aren't `Send`, so they are only kept
+    // as messages
+    let trace_error = |err: serde_reflection::Error| anyhow::anyhow!("{}", err);
+    let mut tracer = Tracer::new(TracerConfig::default());
+    // Enumerations are traced on their own so that all their variants are
+    // explored
+    tracer
+        .trace_simple_type::<ModelSource>()
+        .map_err(trace_error)?;
+    tracer
+        .trace_simple_type::<OutputFormat>()
+        .map_err(trace_error)?;
+    tracer.trace_simple_type::<DType>().map_err(trace_error)?;
+    tracer
+        .trace_simple_type::<Endianness>()
+        .map_err(trace_error)?;
+    tracer
+        .trace_simple_type::<ResolverKind>()
+        .map_err(trace_error)?;
+    tracer.trace_simple_type::<Layout>().map_err(trace_error)?;
+    tracer
+        .trace_simple_type::<TfLiteInferenceService>()
+        .map_err(trace_error)?;
+    let registry = tracer.registry().map_err(trace_error)?;
+    Ok(serde_json::to_string_pretty(&registry)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh directory for the test `name`, under the system's temporary
+    /// directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tflite-nm-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A configuration setting fields of most kinds.
+    fn sample_config() -> TfLiteInferenceService {
+        let mut config = TfLiteInferenceService::new();
+        config.model = ModelSource::Path(PathBuf::from("model.tflite"));
+        config.input_tensor_paths = vec![PathBuf::from("input.bin")];
+        config.output_tensor_paths = vec![PathBuf::from("output.bin")];
+        config.num_threads = 2;
+        config.input_shapes = Some(vec![vec![1, 224, 224, 3]]);
+        config.input_endianness = Some(Endianness::Big);
+        config.output_format = OutputFormat::Csv;
+        config.output_clamp_min = Some(-1.0);
+        config.output_append = true;
+        config
+    }
+
+    #[test]
+    fn config_round_trips_through_json_and_postcard() {
+        let config = sample_config();
+        let json = serde_json::to_vec(&config).unwrap();
+        let postcard = postcard::to_allocvec(&config).unwrap();
+        for input in [json, postcard] {
+            let mut parsed = TfLiteInferenceService::new();
+            assert!(parsed.try_parse(&input).unwrap());
+            assert_eq!(format!("{:?}", parsed), format!("{:?}", config));
+        }
+    }
+
+    #[test]
+    fn json_config_fields_default() {
+        let input = br#"{"model": {"Path": "m.tflite"}, "input_tensor_paths": ["a"],
+            "output_tensor_paths": ["o"], "num_threads": 1}"#;
+        let mut parsed = TfLiteInferenceService::new();
+        assert!(parsed.try_parse(input).unwrap());
+        assert!(parsed.allow_dynamic_tensors);
+        assert!(parsed.overwrite_output);
+        assert_eq!(parsed.output_format, OutputFormat::Raw);
+        parsed.validate().unwrap();
+    }
+
+    #[test]
+    fn gzipped_models_decompress_to_raw_models() {
+        let model = b"TFL3 model bytes".repeat(100);
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&model).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        assert!(gzipped.len() < model.len());
+        assert_eq!(decompress_model(gzipped.clone(), None).unwrap(), model);
+        assert_eq!(decompress_model(model.clone(), None).unwrap(), model);
+        assert!(decompress_model(gzipped, Some(model.len() as u64 - 1)).is_err());
+    }
+
+    #[test]
+    fn missing_output_dirs_are_created_only_if_enabled() {
+        let dir = temp_dir("output-dirs");
+        let output_path = dir.join("nested").join("output.bin");
+        let mut config = TfLiteInferenceService::new();
+        let err = config.create_parent_dir(&output_path).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<InferenceError>(),
+            Some(&InferenceError::OutputIo)
+        );
+        assert!(format!("{:#}", err).contains("doesn't exist"));
+        assert!(!dir.join("nested").exists());
+
+        config.create_output_dirs = true;
+        config.create_parent_dir(&output_path).unwrap();
+        assert!(dir.join("nested").is_dir());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn relative_paths_are_resolved_against_base_dir() {
+        let dir = temp_dir("base-dir");
+        fs::write(dir.join("model.tflite"), b"model").unwrap();
+        let mut config = TfLiteInferenceService::new();
+        config.base_dir = Some(dir.clone());
+
+        // The digest in the error is that of the file read under `base_dir`
+        let model = ModelSource::Path(PathBuf::from("model.tflite"));
+        let other_sha256 = hex::encode(Sha256::digest(b"other"));
+        let err = config
+            .load_model(&model, Some(&other_sha256))
+            .err()
+            .unwrap();
+        let model_sha256 = hex::encode(Sha256::digest(b"model"));
+        assert!(format!("{:#}", err).contains(&model_sha256), "{:#}", err);
+
+        let model = ModelSource::Path(PathBuf::from("missing.tflite"));
+        let err = config.load_model(&model, None).err().unwrap();
+        let missing = format!("{:?}", dir.join("missing.tflite"));
+        assert!(format!("{:#}", err).contains(&missing), "{:#}", err);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn absolute_paths_bypass_base_dir() {
+        let dir = temp_dir("absolute-paths");
+        fs::write(dir.join("model.tflite"), b"model").unwrap();
+        let mut config = TfLiteInferenceService::new();
+        config.base_dir = Some(dir.join("elsewhere"));
+
+        let model = ModelSource::Path(dir.join("model.tflite"));
+        let other_sha256 = hex::encode(Sha256::digest(b"other"));
+        let err = config
+            .load_model(&model, Some(&other_sha256))
+            .err()
+            .unwrap();
+        let model_sha256 = hex::encode(Sha256::digest(b"model"));
+        assert!(format!("{:#}", err).contains(&model_sha256), "{:#}", err);
+        assert_eq!(
+            resolve_stdio_path(config.root(), Path::new(STDIO_PATH)),
+            Path::new("-")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn empty_config_is_not_parsed() {
+        let mut parsed = TfLiteInferenceService::new();
+        assert!(!parsed.try_parse(b"").unwrap());
+        let postcard = postcard::to_allocvec(&sample_config()).unwrap();
+        assert!(parsed.try_parse(&postcard[..postcard.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn output_scaling_undoes_input_normalization_through_npy() {
+        let (mean, std) = (127.5, 127.5);
+        let values = [0.0, 63.75, 127.5, 255.0];
+        let npy = npy::encode(
+            &bytes_from_f32s(&values),
+            ElementKind::kTfLiteFloat32,
+            &[2, 2],
+        )
+        .unwrap();
+        let (header, data) = npy::parse(&npy).unwrap();
+        assert_eq!(header.shape, [2, 2]);
+        let mut normalized = decode_f32s(data, header.element_kind).unwrap();
+        normalize(&mut normalized, mean, std);
+        assert_eq!(normalized, [-1.0, -0.5, 0.0, 1.0]);
+
+        scale_values(&mut normalized, std, mean);
+        let output =
+            npy::encode(&bytes_from_f32s(&normalized), header.element_kind, &[2, 2]).unwrap();
+        assert_eq!(output, npy);
+    }
+
+    #[test]
+    fn outputs_are_clamped_to_one_bound() {
+        let mut values = [-2.0, -0.5, 0.5, 2.0];
+        clamp_values(&mut values, Some(0.0), None);
+        assert_eq!(values, [0.0, 0.0, 0.5, 2.0]);
+        let mut values = [-2.0, -0.5, 0.5, 2.0];
+        clamp_values(&mut values, None, Some(0.0));
+        assert_eq!(values, [-2.0, -0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn outputs_are_clamped_to_both_bounds() {
+        let mut values = [-2.0, -0.5, 0.5, 2.0];
+        clamp_values(&mut values, Some(-1.0), Some(1.0));
+        assert_eq!(values, [-1.0, -0.5, 0.5, 1.0]);
+
+        let mut config = TfLiteInferenceService::new();
+        config.model = ModelSource::Path(PathBuf::from("model.tflite"));
+        config.input_tensor_paths = vec![PathBuf::from("input.bin")];
+        config.output_tensor_paths = vec![PathBuf::from("output.bin")];
+        config.output_clamp_min = Some(1.0);
+        config.output_clamp_max = Some(-1.0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn nchw_tensors_round_trip_through_nhwc_models() {
+        // One image of 2x3 pixels with 2 channels, channel-first
+        let nchw: Vec<u8> = (0..12).collect();
+        let layouts = (Layout::Nhwc, Layout::Nchw);
+        let nhwc = transpose_layout(&nchw, &[1, 2, 3, 2], 1, layouts, true).unwrap();
+        assert_eq!(nhwc, [0, 6, 1, 7, 2, 8, 3, 9, 4, 10, 5, 11]);
+        assert_eq!(
+            transpose_layout(&nhwc, &[1, 2, 3, 2], 1, layouts, false).unwrap(),
+            nchw
+        );
+
+        let layouts = (Layout::Nchw, Layout::Nhwc);
+        assert_eq!(
+            transpose_layout(&nhwc, &[1, 2, 2, 3], 1, layouts, true).unwrap(),
+            nchw
+        );
+        assert!(transpose_layout(&nchw, &[2, 3, 2], 1, layouts, true).is_err());
+    }
+
+    #[test]
+    fn input_endianness_only_applies_to_raw_files() {
+        let mut config = sample_config();
+        config.input_endianness = Some(match Endianness::Big.is_native() {
+            true => Endianness::Little,
+            false => Endianness::Big,
+        });
+        config.validate().unwrap();
+        config.input_tensor_paths = Vec::new();
+        config.input_image_path = Some(PathBuf::from("input.png"));
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("input_endianness"), "{}", err);
+    }
+
+    #[test]
+    fn unclassified_errors_are_not_inference_failures() {
+        let err = classify(anyhow::anyhow!("unexpected"), InferenceError::Other);
+        assert_eq!(err.downcast_ref(), Some(&InferenceError::Other));
+        assert_eq!(InferenceError::Other.exit_code(), 1);
+
+        let err = anyhow::anyhow!("unknown tensor").context(InferenceError::ConfigParse);
+        let err = classify(err.context("while resolving paths"), InferenceError::Other);
+        assert_eq!(err.downcast_ref(), Some(&InferenceError::ConfigParse));
+    }
+
+    #[test]
+    fn only_quantized_outputs_are_dequantized() {
+        let config = TfLiteInferenceService {
+            dequantize_output: true,
+            ..TfLiteInferenceService::new()
+        };
+        for (element_kind, dequantized_kind) in [
+            (ElementKind::kTfLiteUInt8, ElementKind::kTfLiteFloat32),
+            (ElementKind::kTfLiteInt8, ElementKind::kTfLiteFloat32),
+            (ElementKind::kTfLiteFloat32, ElementKind::kTfLiteFloat32),
+            (ElementKind::kTfLiteFloat16, ElementKind::kTfLiteFloat16),
+        ] {
+            assert_eq!(config.output_element_kind(element_kind), dequantized_kind);
+        }
+    }
+
+    #[test]
+    fn malformed_json_config_is_an_error() {
+        let mut parsed = TfLiteInferenceService::new();
+        let err = parsed.try_parse(b"{\"num_threads\": \"two\"}").unwrap_err();
+        assert!(format!("{:#}", err).contains("malformed JSON execution configuration"));
+    }
+}
@@ -1,5 +1,5 @@
-//! A native module for ML inference on TensorFlow Lite.
-//! Takes an input tensor, feeds it to the model and outputs an output tensor.
+//! Entry point of the TensorFlow Lite native module.
+//! Reads the execution configuration and runs the inference it describes.
 //!
 //! ## Authors
 //!
@@ -10,118 +10,265 @@
 //! See the `LICENSE_MIT.markdown` file in the Veracruz root directory for
 //! information on licensing and copyright.
 
-use anyhow;
-use libc::c_int;
-use serde::Deserialize;
+use anyhow::Context;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use log::{error, info};
+use serde::Serialize;
+use std::env;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use tflite::ops::builtin::BuiltinOpResolver;
-use tflite::{FlatBufferModel, InterpreterBuilder};
-use std::time::{SystemTime, UNIX_EPOCH};
-
-/// Module's API.
-#[derive(Deserialize, Debug)]
-pub(crate) struct TfLiteInferenceService {
-    // TODO: support several inputs and outputs
-    /// Path to the input tensor to be fed to the network.
-    input_tensor_path: PathBuf,
-    /// Path to the model serialized with FlatBuffers.
-    model_path: PathBuf,
-    /// Path to the output tensor containing the result of the prediction.
-    output_tensor_path: PathBuf,
-    /// Number of CPU threads to use for the TensorFlow Lite interpreter.
-    num_threads: c_int,
+use std::process;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use tflite_nm::{
+    config_schema, create_file, model_info, run_inference, InferenceError, ModelSource,
+    TfLiteInferenceService,
+};
+
+/// Log file set by `log_path`, log messages going to stderr until then, as
+/// stdout may carry output tensors and reports.
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Failure written to `error_report_path`.
+#[derive(Serialize)]
+struct ErrorReport {
+    /// Class of the failure, if known.
+    category: Option<InferenceError>,
+    /// The error and its causes.
+    message: String,
+    /// Stage of the inference that failed, if known.
+    stage: Option<&'static str>,
 }
 
-impl TfLiteInferenceService {
-    /// Create a new service, with empty internal state.
-    pub fn new() -> Self {
-        Self {
-            input_tensor_path: PathBuf::new(),
-            model_path: PathBuf::new(),
-            output_tensor_path: PathBuf::new(),
-            num_threads: -1,
+/// Destination of the log messages.
+struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match LOG_FILE.get() {
+            Some(file) => file
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .write(buf),
+            None => io::stderr().write(buf),
         }
     }
 
-    /// Try to parse input into a TfLiteInferenceService structure.
-    /// An attacker may inject malformed paths but that should be caught by the
-    /// VFS when attempting to access the corresponding files.
-    /// The input tensor might not match the model's input dimensions, but this
-    /// will be caught by TensorFlow Lite.
-    fn try_parse(&mut self, input: &[u8]) -> anyhow::Result<bool> {
-        let deserialized_input: TfLiteInferenceService =
-            match postcard::from_bytes(&input) {
-                Ok(o) => o,
-                Err(_) => return Ok(false),
-            };
-        *self = deserialized_input;
-        Ok(true)
+    fn flush(&mut self) -> io::Result<()> {
+        match LOG_FILE.get() {
+            Some(file) => file.lock().unwrap_or_else(PoisonError::into_inner).flush(),
+            None => io::stderr().flush(),
+        }
     }
+}
+
+/// Flushes the log when dropped, so that it is flushed on every exit path,
+/// including panics.
+struct FlushLog;
 
-    /// The core service. It loads the model pointed by `model_path` then feeds
-    /// the input read from `input_tensor_path` to the model, and writes the
-    /// resulting tensor to the file at `output_tensor_path`.
-    /// The interpreter can be further configured with `num_threads`.
-    fn infer(&mut self) -> anyhow::Result<()> {
-        let TfLiteInferenceService {
-            input_tensor_path,
-            model_path,
-            output_tensor_path,
-            num_threads,
-        } = self;
-
-        // Build model and interpreter
-        let model = FlatBufferModel::build_from_file(model_path)?;
-        let resolver = BuiltinOpResolver::default();
-        let builder = InterpreterBuilder::new(&model, &resolver)?;
-        let mut interpreter = builder.build()?;
-
-        // Configure interpreter
-        interpreter.set_num_threads(*num_threads);
-
-        interpreter.allocate_tensors()?;
-
-        // Load and configure inputs.
-        // XXX: We assume a single input for now
-        let inputs = interpreter.inputs().to_vec();
-        assert_eq!(inputs.len(), 1);
-        let input_index = inputs[0];
-        let mut input_file = File::open(&input_tensor_path)?;
-        input_file.read_exact(
-            interpreter.tensor_data_mut(input_index)?
-        )?;
-
-        println!("invoking...");
-        interpreter.invoke()?;
-
-        // Get outputs
-        // XXX: We assume a single output for now
-        let outputs = interpreter.outputs().to_vec();
-        let output_index = outputs[0];
-        let output = interpreter
-            .tensor_data(output_index)?;
-
-        println!("writing results...");
-        let mut file = File::create(Path::new("/").join(output_tensor_path))?;
-        file.write_all(&output.to_vec())?;
-
-        Ok(())
+impl Drop for FlushLog {
+    fn drop(&mut self) {
+        if let Err(err) = LogWriter.flush() {
+            eprintln!("Error: failed to flush the log: {}", err);
+        }
     }
 }
 
-fn main() -> anyhow::Result<()>
-{
+fn main() {
+    // Lifecycle messages are only shown if enabled with `RUST_LOG`
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+        .target(env_logger::Target::Pipe(Box::new(LogWriter)))
+        .init();
+    let flush_log = FlushLog;
+    let mut error_report = None;
+    let result = run(&mut error_report);
+    // Failures are logged too, so that the log file tells how the run ended
+    if let (Err(err), Some(_)) = (&result, LOG_FILE.get()) {
+        error!("{:?}", err);
+    }
+    // `process::exit()` doesn't run destructors, so the log file is flushed
+    // beforehand
+    drop(flush_log);
+    if let Err(err) = result {
+        eprintln!("Error: {:?}", err);
+        let category = err.downcast_ref::<InferenceError>().copied();
+        if let Some((error_report_path, overwrite)) = error_report {
+            let report = ErrorReport {
+                category,
+                message: format!("{:#}", err),
+                stage: category.map(InferenceError::stage),
+            };
+            if let Err(err) = write_error_report(&error_report_path, overwrite, &report) {
+                eprintln!(
+                    "Error: failed to write error report {:?}: {}",
+                    error_report_path, err
+                );
+            }
+        }
+        process::exit(category.map_or(1, InferenceError::exit_code));
+    }
+}
+
+/// Write `report` as JSON to `path`, failing if it exists unless `overwrite`
+/// is set.
+fn write_error_report(path: &Path, overwrite: bool, report: &ErrorReport) -> anyhow::Result<()> {
+    create_file(path, overwrite)?.write_all(&serde_json::to_vec_pretty(report)?)?;
+    Ok(())
+}
+
+/// Run the inference configured by the command line, setting `error_report`
+/// to the error report's path, and whether it may be overwritten, once known.
+fn run(error_report: &mut Option<(PathBuf, bool)>) -> anyhow::Result<()> {
+    let mut args = env::args().skip(1).collect::<Vec<_>>();
+
+    if args.first().map(String::as_str) == Some("info") {
+        return match args.as_slice() {
+            [_, model_path] => {
+                println!("{}", model_info(Path::new(model_path))?);
+                Ok(())
+            }
+            _ => Err(anyhow::anyhow!("usage: tflite-nm info <model_path>"))
+                .context(InferenceError::ConfigParse),
+        };
+    }
+    if args == ["--print-schema"] {
+        println!("{}", config_schema()?);
+        return Ok(());
+    }
+
+    // The configuration is given by the first positional argument, by
+    // `TFLITE_NM_CONFIG_B64` or by the path in `TFLITE_NM_CONFIG`, in that
+    // order, and is read from `/execution_config` by default, with any
+    // command-line arguments applied on top of it; only when that file doesn't
+    // exist is the configuration given by command-line arguments alone
+    let config_b64 = env::var_os("TFLITE_NM_CONFIG_B64");
+    let config_path = match args.first() {
+        Some(arg) if !arg.starts_with("--") => Some(PathBuf::from(args.remove(0))),
+        _ if config_b64.is_some() => None,
+        _ => env::var_os("TFLITE_NM_CONFIG").map(PathBuf::from),
+    };
+    let config_path = match config_path {
+        Some(config_path) => Some(config_path),
+        None if config_b64.is_none() => {
+            let default_path = PathBuf::from("/execution_config");
+            if args.is_empty() || default_path.exists() {
+                Some(default_path)
+            } else {
+                None
+            }
+        }
+        None => None,
+    };
+
+    let config = match (config_path, config_b64) {
+        (Some(config_path), _) => {
+            // Read input from execution configuration file
+            info!("opening execution configuration file {:?}...", config_path);
+            let mut f = File::open(&config_path)
+                .with_context(|| format!("failed to open {:?}", config_path))
+                .context(InferenceError::ConfigParse)?;
+            let mut input = Vec::new();
+            info!("reading execution configuration file...");
+            f.read_to_end(&mut input)
+                .context(InferenceError::ConfigParse)?;
+            Some((format!("{:?}", config_path), input))
+        }
+        (None, Some(config_b64)) => {
+            info!("decoding execution configuration from TFLITE_NM_CONFIG_B64...");
+            let input = config_b64
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("TFLITE_NM_CONFIG_B64 is not valid Unicode"))
+                .and_then(|config_b64| Ok(BASE64_STANDARD.decode(config_b64.trim())?))
+                .context("failed to decode TFLITE_NM_CONFIG_B64")
+                .context(InferenceError::ConfigParse)?;
+            Some((String::from("from TFLITE_NM_CONFIG_B64"), input))
+        }
+        (None, None) => None,
+    };
+
+    // Authenticated configurations can neither be overridden nor bypassed
+    if env::var_os("TFLITE_NM_CONFIG_KEY").is_some() {
+        if config.is_none() {
+            return Err(anyhow::anyhow!(
+                "TFLITE_NM_CONFIG_KEY is set but no execution configuration was given"
+            ))
+            .context(InferenceError::ConfigParse);
+        }
+        if !args.is_empty() {
+            return Err(anyhow::anyhow!(
+                "command-line overrides are not allowed when TFLITE_NM_CONFIG_KEY is set"
+            ))
+            .context(InferenceError::ConfigParse);
+        }
+    }
+
     let mut service = TfLiteInferenceService::new();
+    match config {
+        Some((source, input)) => {
+            info!("parsing input...");
+            // Empty or truncated configurations would otherwise leave the
+            // defaults in place and fail confusingly later on
+            let parsed = service
+                .try_parse(&input)
+                .with_context(|| format!("failed to parse execution config {}", source))
+                .context(InferenceError::ConfigParse)?;
+            if !parsed {
+                return Err(anyhow::anyhow!("the execution configuration is empty"))
+                    .with_context(|| format!("failed to parse execution config {}", source))
+                    .context(InferenceError::ConfigParse);
+            }
+        }
+        // Standalone runs resolve relative paths against the working directory
+        None => service.output_root = Some(PathBuf::from(".")),
+    }
+    apply_args(&mut service, &args).context(InferenceError::ConfigParse)?;
+    *error_report = service
+        .error_report_path
+        .as_ref()
+        .map(|path| (service.root().join(path), service.overwrite_output));
+    if let Some(log_path) = &service.log_path {
+        let log_path = service.root().join(log_path);
+        let file = service
+            .create_file(&log_path)
+            .with_context(|| format!("failed to create log file {:?}", log_path))
+            .context(InferenceError::ConfigParse)?;
+        // The log file can only be set once, by the single execution
+        // configuration
+        let _ = LOG_FILE.set(Mutex::new(file));
+    }
+    run_inference(&service)
+}
 
-    // Read input from execution configuration file
-    println!("opening execution configuration file...");
-    let mut f = File::open("/execution_config")?;
-    let mut input = Vec::new();
-    println!("reading execution configuration file...");
-    f.read_to_end(&mut input)?;
-    println!("parsing input...");
-    service.try_parse(&input)?;
-    service.infer()
+/// Override the fields of `service` with the `--model`, `--input`,
+/// `--output` and `--threads` command-line arguments. `--input` and
+/// `--output` can be repeated, once per tensor.
+fn apply_args(service: &mut TfLiteInferenceService, args: &[String]) -> anyhow::Result<()> {
+    let mut input_tensor_paths = Vec::new();
+    let mut output_tensor_paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("missing value for argument {}", arg))
+        };
+        match arg.as_str() {
+            "--model" => service.model = ModelSource::Path(PathBuf::from(value()?)),
+            "--input" => input_tensor_paths.push(PathBuf::from(value()?)),
+            "--output" => output_tensor_paths.push(PathBuf::from(value()?)),
+            "--threads" => {
+                let value = value()?;
+                service.num_threads = value
+                    .parse()
+                    .with_context(|| format!("invalid number of threads {:?}", value))?;
+            }
+            _ => anyhow::bail!("unknown argument {:?}", arg),
+        }
+    }
+    if !input_tensor_paths.is_empty() {
+        service.input_tensor_paths = input_tensor_paths;
+    }
+    if !output_tensor_paths.is_empty() {
+        service.output_tensor_paths = output_tensor_paths;
+    }
+    Ok(())
 }
@@ -0,0 +1,196 @@
+//! Minimal reader and writer for NumPy's `.npy` format, so that tensors can
+//! be exchanged with Python pipelines without stripping or adding headers.
+//! Only C-ordered arrays in the host's byte order are supported.
+//!
+//! See <https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html>
+//! for the format's specification.
+//!
+//! ## Authors
+//!
+//! The Veracruz Development Team.
+//!
+//! ## Licensing and copyright notice
+//!
+//! See the `LICENSE_MIT.markdown` file in the Veracruz root directory for
+//! information on licensing and copyright.
+
+use tflite::context::ElementKind;
+
+/// Magic string starting every `.npy` file.
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// The header is padded so that the data starts at a multiple of this.
+const ALIGNMENT: usize = 64;
+
+/// Shape and element type of an array, as described by a `.npy` header.
+#[derive(Debug)]
+pub(crate) struct Header {
+    pub element_kind: ElementKind,
+    pub shape: Vec<usize>,
+}
+
+/// NumPy type descriptor of an element type, in the host's byte order.
+fn descr(element_kind: ElementKind) -> Option<&'static str> {
+    let little_endian = cfg!(target_endian = "little");
+    Some(match element_kind {
+        ElementKind::kTfLiteFloat32 if little_endian => "<f4",
+        ElementKind::kTfLiteFloat32 => ">f4",
+        ElementKind::kTfLiteFloat16 if little_endian => "<f2",
+        ElementKind::kTfLiteFloat16 => ">f2",
+        ElementKind::kTfLiteInt64 if little_endian => "<i8",
+        ElementKind::kTfLiteInt64 => ">i8",
+        ElementKind::kTfLiteInt32 if little_endian => "<i4",
+        ElementKind::kTfLiteInt32 => ">i4",
+        ElementKind::kTfLiteInt16 if little_endian => "<i2",
+        ElementKind::kTfLiteInt16 => ">i2",
+        ElementKind::kTfLiteUInt8 => "|u1",
+        ElementKind::kTfLiteInt8 => "|i1",
+        ElementKind::kTfLiteBool => "|b1",
+        _ => return None,
+    })
+}
+
+/// Element type described by a NumPy type descriptor, if it is supported.
+/// `=` (native) byte orders are accepted, as well as missing byte orders for
+/// single-byte types.
+fn element_kind(descr: &str) -> Option<ElementKind> {
+    let native = if cfg!(target_endian = "little") {
+        '<'
+    } else {
+        '>'
+    };
+    let descr = match descr.strip_prefix('=') {
+        Some(rest) => format!("{}{}", native, rest),
+        None if descr.len() == 2 => format!("|{}", descr),
+        None => descr.to_string(),
+    };
+    [
+        ElementKind::kTfLiteFloat32,
+        ElementKind::kTfLiteFloat16,
+        ElementKind::kTfLiteInt64,
+        ElementKind::kTfLiteInt32,
+        ElementKind::kTfLiteInt16,
+        ElementKind::kTfLiteUInt8,
+        ElementKind::kTfLiteInt8,
+        ElementKind::kTfLiteBool,
+    ]
+    .into_iter()
+    .find(|element_kind| self::descr(*element_kind) == Some(descr.as_str()))
+}
+
+/// Extract the raw value of `key` from the Python dict literal of a header.
+fn dict_value<'a>(dict: &'a str, key: &str) -> anyhow::Result<&'a str> {
+    let quoted = format!("'{}':", key);
+    let start = dict
+        .find(&quoted)
+        .ok_or_else(|| anyhow::anyhow!("missing {:?} in .npy header", key))?
+        + quoted.len();
+    let value = dict[start..].trim_start();
+    // Values are either strings, booleans or tuples, none of which contain
+    // commas outside of parentheses
+    let end = if value.starts_with('(') {
+        value.find(')').map(|end| end + 1)
+    } else {
+        value.find([',', '}'])
+    }
+    .ok_or_else(|| anyhow::anyhow!("malformed {:?} in .npy header", key))?;
+    Ok(value[..end].trim())
+}
+
+/// Parse a `.npy` file into its header and data.
+pub(crate) fn parse(bytes: &[u8]) -> anyhow::Result<(Header, &[u8])> {
+    if !bytes.starts_with(MAGIC) || bytes.len() < MAGIC.len() + 2 {
+        anyhow::bail!("not a .npy file");
+    }
+    let major_version = bytes[MAGIC.len()];
+    let (header_len, header_start) = match major_version {
+        1 => {
+            let len = bytes
+                .get(8..10)
+                .ok_or_else(|| anyhow::anyhow!("truncated .npy header"))?;
+            (u16::from_le_bytes([len[0], len[1]]) as usize, 10)
+        }
+        2 | 3 => {
+            let len = bytes
+                .get(8..12)
+                .ok_or_else(|| anyhow::anyhow!("truncated .npy header"))?;
+            (
+                u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize,
+                12,
+            )
+        }
+        _ => anyhow::bail!("unsupported .npy format version {}", major_version),
+    };
+    let data_start = header_start + header_len;
+    let dict = bytes
+        .get(header_start..data_start)
+        .ok_or_else(|| anyhow::anyhow!("truncated .npy header"))?;
+    let dict = std::str::from_utf8(dict)?;
+
+    let descr = dict_value(dict, "descr")?.trim_matches('\'');
+    let element_kind = element_kind(descr)
+        .ok_or_else(|| anyhow::anyhow!("unsupported .npy element type {:?}", descr))?;
+    let shape = dict_value(dict, "shape")?
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()?;
+    if dict_value(dict, "fortran_order")? != "False" && shape.len() > 1 {
+        anyhow::bail!("Fortran-ordered .npy arrays are not supported");
+    }
+
+    Ok((
+        Header {
+            element_kind,
+            shape,
+        },
+        &bytes[data_start..],
+    ))
+}
+
+/// Encode raw tensor data as a `.npy` file.
+pub(crate) fn encode(
+    data: &[u8],
+    element_kind: ElementKind,
+    shape: &[usize],
+) -> anyhow::Result<Vec<u8>> {
+    let descr = descr(element_kind).ok_or_else(|| {
+        anyhow::anyhow!(
+            "cannot encode {} tensors as .npy",
+            crate::type_name(element_kind)
+        )
+    })?;
+    let shape = match shape {
+        [dim] => format!("({},)", dim),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|dim| dim.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut dict = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // Pad with spaces and a final newline so that the data is aligned
+    let unpadded_len = MAGIC.len() + 4 + dict.len() + 1;
+    let padding = (ALIGNMENT - unpadded_len % ALIGNMENT) % ALIGNMENT;
+    dict.push_str(&" ".repeat(padding));
+    dict.push('\n');
+    let header_len = u16::try_from(dict.len())
+        .map_err(|_| anyhow::anyhow!("tensor shape too large for a .npy header"))?;
+
+    let mut bytes = Vec::with_capacity(MAGIC.len() + 4 + dict.len() + data.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&[1, 0]);
+    bytes.extend_from_slice(&header_len.to_le_bytes());
+    bytes.extend_from_slice(dict.as_bytes());
+    bytes.extend_from_slice(data);
+    Ok(bytes)
+}
@@ -0,0 +1,232 @@
+//! Server mode of the native module, answering inference requests over a
+//! Unix domain socket so that the model is only loaded once.
+//!
+//! Each connection carries a single request: the input tensors, in the input
+//! blob format (a little-endian u32 number of tensors followed by each tensor
+//! as a little-endian u64 length and its data). The output tensors are sent
+//! back in the same format before the connection is closed.
+//!
+//! ## Authors
+//!
+//! The Veracruz Development Team.
+//!
+//! ## Licensing and copyright notice
+//!
+//! See the `LICENSE_MIT.markdown` file in the Veracruz root directory for
+//! information on licensing and copyright.
+
+use anyhow::Context;
+use log::{info, warn};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Set by `SIGTERM` to stop accepting requests.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// How often the listener checks for a shutdown while idle.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a client may stall reading or writing before its connection is
+/// dropped, so that a single client cannot block the server.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+extern "C" fn on_sigterm(_: libc::c_int) {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+/// Limits of the requests, so that a client cannot exhaust the server's
+/// memory.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RequestLimits {
+    /// Maximum number of tensors, the number of the model's inputs.
+    pub(crate) max_tensors: usize,
+    /// Optional maximum size of each tensor in bytes, as `max_input_bytes`.
+    pub(crate) max_tensor_bytes: Option<u64>,
+}
+
+/// Read a sequence of length-prefixed tensors from `reader`, rejecting
+/// sequences exceeding `limits`.
+fn read_tensors(reader: &mut impl Read, limits: RequestLimits) -> io::Result<Vec<Vec<u8>>> {
+    let mut count = [0; 4];
+    reader.read_exact(&mut count)?;
+    let count = u32::from_le_bytes(count);
+    if usize::try_from(count).map_or(true, |count| count > limits.max_tensors) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} tensors, more than the {} expected",
+                count, limits.max_tensors
+            ),
+        ));
+    }
+    let mut tensors = Vec::new();
+    for _ in 0..count {
+        let mut len = [0; 8];
+        reader.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        if let Some(limit) = limits.max_tensor_bytes.filter(|limit| len > *limit) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "tensor of {} bytes, larger than the limit of {} bytes",
+                    len, limit
+                ),
+            ));
+        }
+        // The buffer only grows as data arrives, whatever the announced length
+        let mut tensor = Vec::new();
+        reader.take(len).read_to_end(&mut tensor)?;
+        if tensor.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        tensors.push(tensor);
+    }
+    Ok(tensors)
+}
+
+/// Write a sequence of length-prefixed tensors to `writer`.
+fn write_tensors(writer: &mut impl Write, tensors: &[Vec<u8>]) -> io::Result<()> {
+    let count = u32::try_from(tensors.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many tensors"))?;
+    writer.write_all(&count.to_le_bytes())?;
+    for tensor in tensors {
+        writer.write_all(&(tensor.len() as u64).to_le_bytes())?;
+        writer.write_all(tensor)?;
+    }
+    writer.flush()
+}
+
+/// Answer the request of a single connection with `infer`.
+fn handle(
+    stream: &mut UnixStream,
+    limits: RequestLimits,
+    infer: &mut impl FnMut(&[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>>,
+) -> anyhow::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    let inputs = read_tensors(stream, limits).context("failed to read the request")?;
+    let outputs = infer(&inputs)?;
+    write_tensors(stream, &outputs).context("failed to write the response")
+}
+
+/// Listen on `socket_path` and answer each request within `limits` with
+/// `infer`, until `SIGTERM` is received. Failed requests are logged and their
+/// connections closed without a response.
+pub(crate) fn serve(
+    socket_path: &Path,
+    limits: RequestLimits,
+    mut infer: impl FnMut(&[Vec<u8>]) -> anyhow::Result<Vec<Vec<u8>>>,
+) -> anyhow::Result<()> {
+    // Sockets left behind by a previous server are replaced, but no other file
+    if let Ok(metadata) = socket_path.symlink_metadata() {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(socket_path)?;
+        }
+    }
+    let listener = UnixListener::bind(socket_path)
+        .with_context(|| format!("failed to listen on {:?}", socket_path))?;
+    // The listener is polled so that a shutdown is noticed while idle
+    listener.set_nonblocking(true)?;
+    // Without `SA_RESTART`, a blocking call interrupted by the signal returns
+    // instead of resuming, so that the shutdown is noticed
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe,
+    // and the action is fully initialized before being installed
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_sigterm;
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = 0;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error()).context("failed to handle SIGTERM");
+        }
+    }
+
+    info!("serving on {:?}...", socket_path);
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                if let Err(err) = handle(&mut stream, limits, &mut infer) {
+                    warn!("request failed: {:?}", err);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err).context("failed to accept a connection"),
+        }
+    }
+    info!("shutting down...");
+    std::fs::remove_file(socket_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    const LIMITS: RequestLimits = RequestLimits {
+        max_tensors: 1,
+        max_tensor_bytes: Some(16),
+    };
+
+    /// Encode `tensors` as a request.
+    fn request(tensors: &[Vec<u8>]) -> Vec<u8> {
+        let mut request = Vec::new();
+        write_tensors(&mut request, tensors).unwrap();
+        request
+    }
+
+    #[test]
+    fn requests_exceeding_the_limits_are_rejected() {
+        let tensors = vec![vec![1; 16]];
+        assert_eq!(
+            read_tensors(&mut &request(&tensors)[..], LIMITS).unwrap(),
+            tensors
+        );
+        let too_many = request(&[vec![1], vec![2]]);
+        assert!(read_tensors(&mut &too_many[..], LIMITS).is_err());
+        // Only the length prefix is sent, the data never is
+        let mut too_large = 1u32.to_le_bytes().to_vec();
+        too_large.extend(u64::MAX.to_le_bytes());
+        let err = read_tensors(&mut &too_large[..], LIMITS).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn server_survives_oversized_requests() {
+        let socket_path = env::temp_dir().join(format!("tflite-nm-{}.sock", std::process::id()));
+        let server = {
+            let socket_path = socket_path.clone();
+            thread::spawn(move || serve(&socket_path, LIMITS, |inputs| Ok(inputs.to_vec())))
+        };
+        let connect = || loop {
+            match UnixStream::connect(&socket_path) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(POLL_INTERVAL),
+            }
+        };
+
+        let mut stream = connect();
+        stream.write_all(&1u32.to_le_bytes()).unwrap();
+        stream.write_all(&u64::MAX.to_le_bytes()).unwrap();
+        // The connection is closed without a response
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert!(response.is_empty());
+
+        let tensors = vec![vec![1, 2, 3]];
+        let mut stream = connect();
+        stream.write_all(&request(&tensors)).unwrap();
+        assert_eq!(read_tensors(&mut stream, LIMITS).unwrap(), tensors);
+
+        SHUTDOWN.store(true, Ordering::SeqCst);
+        server.join().unwrap().unwrap();
+    }
+}
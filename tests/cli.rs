@@ -0,0 +1,154 @@
+//! Tests of the command-line interface of the native module.
+//!
+//! ## Authors
+//!
+//! The Veracruz Development Team.
+//!
+//! ## Licensing and copyright notice
+//!
+//! See the `LICENSE_MIT.markdown` file in the Veracruz root directory for
+//! information on licensing and copyright.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Fresh directory for the test `name`, under the system's temporary
+/// directory.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("tflite-nm-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run the native module with `args`.
+fn tflite_nm(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tflite-nm"))
+        .args(args)
+        .env_remove("TFLITE_NM_CONFIG")
+        .env_remove("TFLITE_NM_CONFIG_B64")
+        .env_remove("TFLITE_NM_CONFIG_KEY")
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn empty_config_fails_to_parse() {
+    let dir = temp_dir("empty-config");
+    let config_path = dir.join("execution_config");
+    fs::write(&config_path, b"").unwrap();
+    let output = tflite_nm(&[config_path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("failed to parse execution config"),
+        "{}",
+        stderr
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn authenticated_configs_cannot_be_overridden() {
+    let dir = temp_dir("config-key");
+    let key_path = dir.join("key");
+    fs::write(&key_path, b"secret").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_tflite-nm"))
+        .args(["--model", "model.tflite"])
+        .env_remove("TFLITE_NM_CONFIG")
+        .env_remove("TFLITE_NM_CONFIG_B64")
+        .env("TFLITE_NM_CONFIG_KEY", &key_path)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("TFLITE_NM_CONFIG_KEY"), "{}", stderr);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn failures_are_reported_to_error_report_path() {
+    let dir = temp_dir("error-report");
+    let config_path = dir.join("execution_config");
+    let config = serde_json::json!({
+        "model": { "Path": "missing.tflite" },
+        "input_tensor_paths": ["input.bin"],
+        "output_tensor_paths": ["output.bin"],
+        "output_root": dir,
+        "error_report_path": "error.json",
+    });
+    fs::write(&config_path, config.to_string()).unwrap();
+    let output = tflite_nm(&[config_path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(3));
+    let report: serde_json::Value =
+        serde_json::from_slice(&fs::read(dir.join("error.json")).unwrap()).unwrap();
+    assert_eq!(report["stage"], "model_load");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn info_without_a_model_prints_its_usage() {
+    for args in [&["info"][..], &["info", "a.tflite", "b.tflite"]] {
+        let output = tflite_nm(args);
+        assert_eq!(output.status.code(), Some(2));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("usage: tflite-nm info <model_path>"),
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn failures_are_flushed_to_log_path() {
+    let dir = temp_dir("log-path");
+    let config_path = dir.join("execution_config");
+    let config = serde_json::json!({
+        "model": { "Path": "missing.tflite" },
+        "input_tensor_paths": ["input.bin"],
+        "output_tensor_paths": ["output.bin"],
+        "output_root": dir,
+        "log_path": "run.log",
+    });
+    fs::write(&config_path, config.to_string()).unwrap();
+    let output = tflite_nm(&[config_path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(3));
+    let log = fs::read_to_string(dir.join("run.log")).unwrap();
+    assert!(log.contains("missing.tflite"), "{}", log);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn arguments_override_the_config_fields() {
+    let dir = temp_dir("override");
+    let config_path = dir.join("execution_config");
+    let config = serde_json::json!({
+        "model": { "Path": "config.tflite" },
+        "input_tensor_paths": ["input.bin"],
+        "output_tensor_paths": ["output.bin"],
+        "output_root": dir,
+        "error_report_path": "error.json",
+    });
+    fs::write(&config_path, config.to_string()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_tflite-nm"))
+        .args(["--model", "flag.tflite"])
+        .env("TFLITE_NM_CONFIG", &config_path)
+        .env_remove("TFLITE_NM_CONFIG_B64")
+        .env_remove("TFLITE_NM_CONFIG_KEY")
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("flag.tflite"), "{}", stderr);
+    assert!(!stderr.contains("config.tflite"), "{}", stderr);
+    // The fields not given on the command line are still read from the file
+    let report: serde_json::Value =
+        serde_json::from_slice(&fs::read(dir.join("error.json")).unwrap()).unwrap();
+    assert_eq!(report["stage"], "model_load");
+    fs::remove_dir_all(dir).unwrap();
+}
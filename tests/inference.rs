@@ -0,0 +1,116 @@
+//! Tests of the library's inference entry points.
+//!
+//! The tests running a model are ignored by default, as they need a
+//! TensorFlow Lite model with float32 inputs at the path given by the
+//! `TFLITE_NM_TEST_MODEL` environment variable:
+//! `TFLITE_NM_TEST_MODEL=model.tflite cargo test -- --ignored`.
+//!
+//! ## Authors
+//!
+//! The Veracruz Development Team.
+//!
+//! ## Licensing and copyright notice
+//!
+//! See the `LICENSE_MIT.markdown` file in the Veracruz root directory for
+//! information on licensing and copyright.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use tflite_nm::{model_info, run_inference, InferenceError, ModelSource, TfLiteInferenceService};
+
+/// A configuration running the model at `model_path`.
+fn config(model_path: PathBuf) -> TfLiteInferenceService {
+    let mut config = TfLiteInferenceService::new();
+    config.model = ModelSource::Path(model_path);
+    config.input_tensor_paths = vec![PathBuf::from("input.bin")];
+    config.output_tensor_paths = vec![PathBuf::from("output.bin")];
+    config.output_root = Some(env::temp_dir());
+    config
+}
+
+#[test]
+fn invalid_config_is_rejected_before_loading_the_model() {
+    let err = run_inference(&config(PathBuf::new())).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<InferenceError>(),
+        Some(&InferenceError::ConfigParse)
+    );
+}
+
+#[test]
+fn invalid_config_is_rejected_in_memory() {
+    let err = config(PathBuf::new()).infer_bytes(&[]).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<InferenceError>(),
+        Some(&InferenceError::ConfigParse)
+    );
+}
+
+#[test]
+fn missing_model_fails_to_load() {
+    let err = run_inference(&config(PathBuf::from("tflite-nm-missing.tflite"))).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<InferenceError>(),
+        Some(&InferenceError::ModelLoad)
+    );
+}
+
+/// The test model, its description and zero-filled float32 inputs for it.
+fn test_model() -> (PathBuf, serde_json::Value, Vec<Vec<u8>>) {
+    let model_path = PathBuf::from(env::var_os("TFLITE_NM_TEST_MODEL").unwrap());
+    let info: serde_json::Value = serde_json::from_str(&model_info(&model_path).unwrap()).unwrap();
+    let inputs = info["inputs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|input| {
+            let dims = input["dims"].as_array().unwrap();
+            let elements = dims
+                .iter()
+                .map(|dim| dim.as_u64().unwrap() as usize)
+                .product::<usize>();
+            vec![0; elements * 4]
+        })
+        .collect::<Vec<_>>();
+    (model_path, info, inputs)
+}
+
+#[test]
+#[ignore = "needs a model at TFLITE_NM_TEST_MODEL"]
+fn in_memory_inference_is_repeatable() {
+    let (model_path, info, inputs) = test_model();
+    let config = config(model_path);
+    let outputs = config.infer_bytes(&inputs).unwrap();
+    assert_eq!(outputs.len(), info["outputs"].as_array().unwrap().len());
+    assert!(outputs.iter().all(|output| !output.is_empty()));
+    assert_eq!(config.infer_bytes(&inputs).unwrap(), outputs);
+}
+
+#[test]
+#[ignore = "needs a model at TFLITE_NM_TEST_MODEL"]
+fn tensor_paths_are_resolved_against_base_dir() {
+    let (model_path, info, inputs) = test_model();
+    let dir = env::temp_dir().join(format!("tflite-nm-base-dir-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    // The model's path is absolute, the tensors' relative to `base_dir`
+    let mut config = config(fs::canonicalize(model_path).unwrap());
+    config.base_dir = Some(dir.clone());
+    config.input_tensor_paths = (0..inputs.len())
+        .map(|i| PathBuf::from(format!("input{}.bin", i)))
+        .collect();
+    config.output_tensor_paths = (0..info["outputs"].as_array().unwrap().len())
+        .map(|i| PathBuf::from(format!("output{}.bin", i)))
+        .collect();
+    for (path, input) in config.input_tensor_paths.iter().zip(&inputs) {
+        fs::write(dir.join(path), input).unwrap();
+    }
+    run_inference(&config).unwrap();
+    let outputs = config.infer_bytes(&inputs).unwrap();
+    for (path, output) in config.output_tensor_paths.iter().zip(outputs) {
+        assert_eq!(fs::read(dir.join(path)).unwrap(), output);
+    }
+    fs::remove_dir_all(dir).unwrap();
+}